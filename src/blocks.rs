@@ -0,0 +1,223 @@
+//! slack block kit messages for new articles and digests
+use crate::digest::DigestEntry;
+use crate::reader::Article;
+use crate::utils::strip_html;
+use serde_json::{json, Value};
+
+// about the length of a tweet, enough to tell what an article is about without taking over the
+// channel
+const SUMMARY_LENGTH: usize = 280;
+// slack rejects section text over 3000 characters, and messages with more than 50 blocks
+const MAX_SECTION_LENGTH: usize = 2900;
+const MAX_BLOCKS: usize = 50;
+
+// slack only needs these three escaped in message text
+pub fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// cut a summary down to SUMMARY_LENGTH characters, at a word boundary where possible
+pub fn trim_summary(summary: &str) -> String {
+    let text = strip_html(summary);
+    if text.chars().count() <= SUMMARY_LENGTH {
+        return text;
+    }
+
+    let cut: String = text.chars().take(SUMMARY_LENGTH).collect();
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(idx) => &cut[..idx],
+        None => &cut,
+    };
+    format!(
+        "{}…",
+        cut.trim_end_matches(|c: char| c.is_ascii_punctuation())
+    )
+}
+
+// the plain text version of an article post, shown in notifications and by clients that can't
+// render blocks
+pub fn article_text(article: &Article) -> String {
+    link(&article.url.to_string(), &article.title.to_string())
+}
+
+pub fn link(url: &str, title: &str) -> String {
+    format!("<{}|{}>", url, escape(title))
+}
+
+pub fn article_blocks(feed_name: &str, article: &Article) -> Value {
+    let mut text = format!("*{}*", article_text(article));
+    if let Some(summary) = &article.summary {
+        let summary = trim_summary(summary);
+        if !summary.is_empty() {
+            text.push('\n');
+            text.push_str(&escape(&summary));
+        }
+    }
+
+    let mut context = vec![escape(feed_name)];
+    if let Some(author) = &article.author {
+        context.push(escape(author));
+    }
+    if let Some(published) = &article.published {
+        // slack shows this in the reader's own timezone, the text after | is the fallback
+        context.push(format!(
+            "<!date^{}^{{date_short_pretty}}|{}>",
+            published.timestamp(),
+            published.format("%b %-d, %Y")
+        ));
+    }
+
+    json!([
+        {
+            "type": "section",
+            "text": { "type": "mrkdwn", "text": text }
+        },
+        {
+            "type": "context",
+            "elements": [{ "type": "mrkdwn", "text": context.join(" · ") }]
+        }
+    ])
+}
+
+pub fn digest_text(entries: &[DigestEntry]) -> String {
+    match entries.len() {
+        1 => String::from("1 new article"),
+        n => format!("{} new articles", n),
+    }
+}
+
+// one section per feed listing its articles, in the order the feeds first turned up
+pub fn digest_blocks(entries: &[DigestEntry]) -> Value {
+    let mut groups: Vec<(&str, Vec<&DigestEntry>)> = Vec::new();
+    for entry in entries {
+        match groups.iter_mut().find(|(name, _)| *name == entry.feed_name) {
+            Some((_, group)) => group.push(entry),
+            None => groups.push((&entry.feed_name, vec![entry])),
+        }
+    }
+
+    let mut sections = Vec::new();
+    for (feed_name, group) in groups {
+        let mut text = format!("*{}*", escape(feed_name));
+        for entry in group {
+            let line = format!("\n• {}", link(&entry.url, &entry.title));
+            if text.len() + line.len() > MAX_SECTION_LENGTH {
+                sections.push(text);
+                text = String::new();
+            }
+            text.push_str(&line);
+        }
+        sections.push(text);
+    }
+
+    let mut blocks = vec![json!({
+        "type": "section",
+        "text": { "type": "mrkdwn", "text": format!("*{}*", digest_text(entries)) }
+    })];
+    // leave room for the header and a note about anything that didn't fit
+    let shown = sections.len().min(MAX_BLOCKS - 2);
+    let hidden = sections.len() - shown;
+    for text in sections.into_iter().take(shown) {
+        blocks.push(json!({ "type": "section", "text": { "type": "mrkdwn", "text": text } }));
+    }
+    if hidden > 0 {
+        blocks.push(json!({
+            "type": "context",
+            "elements": [{ "type": "mrkdwn", "text": format!("and {} more sections", hidden) }]
+        }));
+    }
+    Value::Array(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_summaries_are_kept() {
+        assert_eq!(
+            trim_summary("<p>a <b>short</b> summary &amp; more</p>"),
+            "a short summary & more"
+        );
+        let exact = "x".repeat(SUMMARY_LENGTH);
+        assert_eq!(trim_summary(&exact), exact);
+    }
+
+    #[test]
+    fn long_summaries_are_cut_at_a_word() {
+        let summary = "word, ".repeat(100);
+        let trimmed = trim_summary(&summary);
+        assert!(trimmed.chars().count() <= SUMMARY_LENGTH + 1);
+        assert!(trimmed.ends_with("word…"), "{}", trimmed);
+
+        // one long word can only be cut where it reaches the limit
+        let trimmed = trim_summary(&"é".repeat(SUMMARY_LENGTH * 2));
+        assert_eq!(trimmed, format!("{}…", "é".repeat(SUMMARY_LENGTH)));
+    }
+
+    fn entry(feed_name: &str, n: usize) -> DigestEntry {
+        DigestEntry {
+            feed_name: String::from(feed_name),
+            title: format!("article {}", n),
+            url: format!("https://example.com/{}", n),
+        }
+    }
+
+    fn texts(blocks: &Value) -> Vec<&str> {
+        blocks
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|block| {
+                block["text"]["text"]
+                    .as_str()
+                    .or_else(|| block["elements"][0]["text"].as_str())
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn digests_group_by_feed() {
+        let entries = vec![entry("one", 1), entry("two", 2), entry("one", 3)];
+        let blocks = digest_blocks(&entries);
+        assert_eq!(
+            texts(&blocks),
+            vec![
+                "*3 new articles*",
+                concat!(
+                    "*one*\n• <https://example.com/1|article 1>",
+                    "\n• <https://example.com/3|article 3>"
+                ),
+                "*two*\n• <https://example.com/2|article 2>",
+            ]
+        );
+    }
+
+    #[test]
+    fn long_sections_are_split() {
+        let entries: Vec<DigestEntry> = (0..200).map(|n| entry("one", n)).collect();
+        let blocks = digest_blocks(&entries);
+        let texts = texts(&blocks);
+        assert!(texts.len() > 2);
+        assert!(texts.iter().all(|text| text.len() <= MAX_SECTION_LENGTH));
+        let lines: usize = texts.iter().map(|text| text.matches('•').count()).sum();
+        assert_eq!(lines, 200);
+    }
+
+    #[test]
+    fn at_most_50_blocks() {
+        let entries: Vec<DigestEntry> = (0..60).map(|n| entry(&n.to_string(), n)).collect();
+        let blocks = digest_blocks(&entries);
+        let texts = texts(&blocks);
+        assert_eq!(texts.len(), MAX_BLOCKS);
+        assert_eq!(texts[0], "*60 new articles*");
+        assert_eq!(texts[MAX_BLOCKS - 1], "and 12 more sections");
+
+        // exactly enough room needs no note
+        let entries: Vec<DigestEntry> = (0..48).map(|n| entry(&n.to_string(), n)).collect();
+        assert_eq!(digest_blocks(&entries).as_array().unwrap().len(), 49);
+    }
+}
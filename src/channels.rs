@@ -0,0 +1,134 @@
+//! looking up channel ids by name
+//!
+//! channels are referred to by name everywhere else, and turned into ids with conversations.list.
+//! the ids are cached and the cache is refreshed every so often, or when a lookup misses, so a
+//! channel that was created or renamed while the bot is running is picked up.
+use crate::SlackChannel;
+use failure::{bail, Error};
+use log::{debug, error, info};
+use reqwest::blocking::Client;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// how long the cache is trusted before it's refreshed on the next lookup
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+// a lookup that misses refreshes the cache at most this often, conversations.list is rate limited
+const MIN_REFRESH: Duration = Duration::from_secs(60);
+const PAGE_SIZE: &str = "200";
+
+struct Cache {
+    // channel name -> channel id
+    ids: HashMap<String, String>,
+    refreshed: Instant,
+}
+
+pub struct ChannelDirectory {
+    client: Client,
+    token: String,
+    cache: Mutex<Cache>,
+}
+
+impl ChannelDirectory {
+    // fetch every channel the bot can see, failing if slack can't be reached
+    pub fn load(token: &str) -> Result<ChannelDirectory, Error> {
+        let client = Client::new();
+        let ids = list_channels(&client, token)?;
+        info!("found {} channels", ids.len());
+        Ok(ChannelDirectory {
+            client,
+            token: String::from(token),
+            cache: Mutex::new(Cache {
+                ids,
+                refreshed: Instant::now(),
+            }),
+        })
+    }
+
+    // the channel's id, or None if there's no channel by that name
+    pub fn id(&self, channel: &SlackChannel) -> Option<String> {
+        self.lookup(|ids| ids.get(channel.name()).cloned())
+    }
+
+    // the channel with the given id, or None if the bot can't see it
+    pub fn channel(&self, id: &str) -> Option<SlackChannel> {
+        self.lookup(|ids| {
+            ids.iter()
+                .find(|(_, chan_id)| *chan_id == id)
+                .and_then(|(name, _)| name.parse().ok())
+        })
+    }
+
+    // the channels out of `channels` that don't exist, so that config mistakes show up at startup
+    pub fn missing<'a>(
+        &self,
+        channels: impl IntoIterator<Item = &'a SlackChannel>,
+    ) -> Vec<&'a SlackChannel> {
+        channels
+            .into_iter()
+            .filter(|channel| self.id(channel).is_none())
+            .collect()
+    }
+
+    fn lookup<T>(&self, find: impl Fn(&HashMap<String, String>) -> Option<T>) -> Option<T> {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.refreshed.elapsed() >= CACHE_TTL {
+            self.refresh(&mut cache);
+        }
+        if let Some(found) = find(&cache.ids) {
+            return Some(found);
+        }
+        if cache.refreshed.elapsed() >= MIN_REFRESH {
+            self.refresh(&mut cache);
+            return find(&cache.ids);
+        }
+        None
+    }
+
+    // on failure the old ids are kept, they're more likely right than wrong
+    fn refresh(&self, cache: &mut Cache) {
+        cache.refreshed = Instant::now();
+        match list_channels(&self.client, &self.token) {
+            Ok(ids) => {
+                debug!("refreshed channel ids, found {} channels", ids.len());
+                cache.ids = ids;
+            }
+            Err(e) => error!("unable to refresh channel ids: {}", e),
+        }
+    }
+}
+
+// page through conversations.list, collecting name -> id for every channel that isn't archived
+fn list_channels(client: &Client, token: &str) -> Result<HashMap<String, String>, Error> {
+    let mut ids = HashMap::new();
+    let mut cursor = String::new();
+    loop {
+        let res: Value = client
+            .get("https://slack.com/api/conversations.list")
+            .bearer_auth(token)
+            .query(&[
+                ("types", "public_channel,private_channel"),
+                ("exclude_archived", "true"),
+                ("limit", PAGE_SIZE),
+                ("cursor", &cursor),
+            ])
+            .send()?
+            .json()?;
+
+        if res["ok"].as_bool() != Some(true) {
+            bail!("conversations.list failed: {}", res["error"]);
+        }
+
+        for channel in res["channels"].as_array().into_iter().flatten() {
+            if let (Some(name), Some(id)) = (channel["name"].as_str(), channel["id"].as_str()) {
+                ids.insert(String::from(name), String::from(id));
+            }
+        }
+
+        match res["response_metadata"]["next_cursor"].as_str() {
+            Some(next) if !next.is_empty() => cursor = String::from(next),
+            _ => return Ok(ids),
+        }
+    }
+}
@@ -0,0 +1,420 @@
+//! the ! commands, all in one place
+//!
+//! every command is listed in COMMANDS with its usage, what it does and the channels it works in,
+//! which is also where !help gets its answer from. arguments are split a bit like a shell would:
+//! any amount of whitespace separates them, quotes keep spaces in one, and --flag, --flag=value
+//! or, for flags that take a value, --flag value are flags.
+use crate::library::{
+    edit_tags, last_five_table, parse_put_tags, parse_tag, put_with_reaction, search_table,
+    tags_table,
+};
+use crate::subscriptions::feed_command;
+use crate::utils::{bot_say_to, ReplyTo};
+use crate::Handler;
+use log::{error, info};
+use prettytable::{format, Table};
+use slack_api::MessageStandard;
+use std::collections::HashMap;
+
+#[derive(Debug, Default)]
+pub struct Args {
+    pub positional: Vec<String>,
+    // a flag given without a value maps to None
+    pub flags: HashMap<String, Option<String>>,
+}
+
+impl Args {
+    // the value given to a flag, if it was given one
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.flags.get(name).and_then(|value| value.as_deref())
+    }
+}
+
+// split everything after the command name into arguments. slack turns quotes into curly ones and
+// -- into an em dash, depending on who typed them, so those count too.
+// `value_flags` are the flags that take the argument after them as their value.
+pub fn parse_args(text: &str, value_flags: &[&str]) -> Result<Args, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    for c in text.chars() {
+        match (quote, c) {
+            (Some(open), c) if c == open || (open == '“' && c == '”') => quote = None,
+            (Some(_), c) => word.push(c),
+            // a quote only opens at the start of an argument, so apostrophes are left alone
+            (None, '"') | (None, '\'') | (None, '“') if !in_word => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err(String::from("unterminated quote"));
+    }
+    if in_word {
+        words.push(word);
+    }
+
+    let mut args = Args::default();
+    let mut words = words.into_iter();
+    while let Some(word) = words.next() {
+        let flag = word
+            .strip_prefix("--")
+            .or_else(|| word.strip_prefix('—'))
+            .filter(|flag| !flag.is_empty());
+        match flag {
+            Some(flag) => {
+                let (name, value) = match flag.split_once('=') {
+                    Some((name, value)) => (name, Some(String::from(value))),
+                    None if value_flags.contains(&flag) => match words.next() {
+                        Some(value) => (flag, Some(value)),
+                        None => return Err(format!("--{} needs a value", flag)),
+                    },
+                    None => (flag, None),
+                };
+                args.flags.insert(String::from(name), value);
+            }
+            None => args.positional.push(word),
+        }
+    }
+    Ok(args)
+}
+
+// the channels, by name, that a command works in
+pub enum Allowed {
+    Anywhere,
+    Only(&'static [&'static str]),
+}
+
+// everything a command gets to work with
+pub struct Context<'a> {
+    pub handler: &'a Handler,
+    pub message: &'a MessageStandard,
+    // the id of the channel the command was sent in
+    pub channel: &'a str,
+}
+
+pub struct Command {
+    pub name: &'static str,
+    // without the leading !, so that /library can share it
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub allowed: Allowed,
+    // how many positional arguments the command takes, at least and at most
+    pub args: (usize, usize),
+    // flags that are either there or not
+    pub flags: &'static [&'static str],
+    pub value_flags: &'static [&'static str],
+    run: fn(&Context, &Args) -> Result<(), String>,
+}
+
+const LIBRARY_CHANNELS: &[&str] = &["library", "botspam"];
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "feed",
+        usage: "feed add <url> [#channel] | remove <url> | list",
+        help: "change which feeds are posted where",
+        allowed: Allowed::Anywhere,
+        args: (1, 3),
+        flags: &[],
+        value_flags: &[],
+        run: run_feed,
+    },
+    Command {
+        name: "put",
+        usage: "put <url> [#tag ...]",
+        help: "save a link to the library",
+        allowed: Allowed::Only(LIBRARY_CHANNELS),
+        args: (1, usize::MAX),
+        flags: &[],
+        value_flags: &[],
+        run: run_put,
+    },
+    Command {
+        name: "last",
+        usage: "last [--tag <tag>]",
+        help: "the five newest links in the library, or the five newest with the tag",
+        allowed: Allowed::Only(LIBRARY_CHANNELS),
+        args: (0, 0),
+        flags: &[],
+        value_flags: &["tag"],
+        run: run_last,
+    },
+    Command {
+        name: "tag",
+        usage: "tag <id> +tag -tag ...",
+        help: "add tags to a link and take them off, by the id !last shows",
+        allowed: Allowed::Only(LIBRARY_CHANNELS),
+        args: (2, usize::MAX),
+        flags: &[],
+        value_flags: &[],
+        run: run_tag,
+    },
+    Command {
+        name: "tags",
+        usage: "tags",
+        help: "every tag in the library and how many links have it",
+        allowed: Allowed::Only(LIBRARY_CHANNELS),
+        args: (0, 0),
+        flags: &[],
+        value_flags: &[],
+        run: run_tags,
+    },
+    Command {
+        name: "search",
+        usage: "search <terms>",
+        help: "the five links that best match the terms",
+        allowed: Allowed::Only(LIBRARY_CHANNELS),
+        args: (1, usize::MAX),
+        flags: &[],
+        value_flags: &[],
+        run: run_search,
+    },
+    Command {
+        name: "help",
+        usage: "help",
+        help: "this list",
+        allowed: Allowed::Anywhere,
+        args: (0, 0),
+        flags: &[],
+        value_flags: &[],
+        run: run_help,
+    },
+];
+
+fn run_feed(ctx: &Context, args: &Args) -> Result<(), String> {
+    let words: Vec<&str> = args.positional.iter().map(String::as_str).collect();
+    let reply_to = ReplyTo::Channel(String::from(ctx.channel));
+    feed_command(
+        "!feed",
+        &words,
+        ctx.channel,
+        &reply_to,
+        &ctx.handler.feed_commands,
+        &ctx.handler.workspace,
+    );
+    Ok(())
+}
+
+fn run_put(ctx: &Context, args: &Args) -> Result<(), String> {
+    let tags = parse_put_tags(&args.positional[1..])?;
+    put_with_reaction(
+        &args.positional[0],
+        &tags,
+        ctx.message,
+        &ctx.handler.workspace,
+    )
+}
+
+fn run_last(ctx: &Context, args: &Args) -> Result<(), String> {
+    let workspace = &ctx.handler.workspace;
+    let tag = args.value("tag").map(parse_tag).transpose()?;
+    bot_say_to(
+        workspace,
+        ctx.channel,
+        &last_five_table(tag.as_deref(), workspace),
+    );
+    Ok(())
+}
+
+fn run_tag(ctx: &Context, args: &Args) -> Result<(), String> {
+    let workspace = &ctx.handler.workspace;
+    let msg = edit_tags(&args.positional[0], &args.positional[1..], workspace)?;
+    bot_say_to(workspace, ctx.channel, &msg);
+    Ok(())
+}
+
+fn run_tags(ctx: &Context, _: &Args) -> Result<(), String> {
+    let workspace = &ctx.handler.workspace;
+    bot_say_to(workspace, ctx.channel, &tags_table(workspace));
+    Ok(())
+}
+
+fn run_search(ctx: &Context, args: &Args) -> Result<(), String> {
+    let workspace = &ctx.handler.workspace;
+    let terms: Vec<&str> = args.positional.iter().map(String::as_str).collect();
+    bot_say_to(workspace, ctx.channel, &search_table(&terms, workspace));
+    Ok(())
+}
+
+fn run_help(ctx: &Context, _: &Args) -> Result<(), String> {
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(row!["command", "what it does", "where"]);
+    for command in COMMANDS {
+        let allowed = match command.allowed {
+            Allowed::Anywhere => String::from("anywhere"),
+            Allowed::Only(names) => {
+                let names: Vec<String> = names.iter().map(|n| format!("#{}", n)).collect();
+                names.join(", ")
+            }
+        };
+        table.add_row(row![command.usage("!"), command.help, allowed]);
+    }
+    bot_say_to(&ctx.handler.workspace, ctx.channel, &table.to_string());
+    Ok(())
+}
+
+impl Command {
+    pub fn find(name: &str) -> Option<&'static Command> {
+        COMMANDS.iter().find(|command| command.name == name)
+    }
+
+    fn allowed_in(&self, ctx: &Context) -> bool {
+        match self.allowed {
+            Allowed::Anywhere => true,
+            Allowed::Only(names) => ctx
+                .handler
+                .workspace
+                .channels
+                .channel(ctx.channel)
+                .is_some_and(|channel| names.contains(&channel.name())),
+        }
+    }
+
+    // how to use the command when it's typed after `prefix`, like "!" or "/library "
+    pub fn usage(&self, prefix: &str) -> String {
+        format!("{}{}", prefix, self.usage)
+    }
+
+    // split the text after the command into arguments and make sure the command takes them
+    pub fn parse(&self, text: &str, prefix: &str) -> Result<Args, String> {
+        let args = parse_args(text, self.value_flags)?;
+        let (min, max) = self.args;
+        let count = args.positional.len();
+        if count < min || count > max {
+            return Err(format!("usage: {}", self.usage(prefix)));
+        }
+        let known = |flag: &str| self.flags.contains(&flag) || self.value_flags.contains(&flag);
+        if let Some(flag) = args.flags.keys().find(|flag| !known(flag)) {
+            return Err(format!(
+                "unknown flag --{}, usage: {}",
+                flag,
+                self.usage(prefix)
+            ));
+        }
+        Ok(args)
+    }
+}
+
+// run the command in a message, if there is one. returns whether the message was a command.
+pub fn dispatch(handler: &Handler, message: &MessageStandard) -> bool {
+    let text = message.text.as_deref().unwrap_or_default();
+    let channel = message.channel.as_deref().unwrap_or_default();
+    let rest = match text.strip_prefix('!') {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let (name, arg_text) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    let command = match Command::find(name) {
+        Some(command) => command,
+        None => return false,
+    };
+    info!("matched !{}", command.name);
+
+    let ctx = Context {
+        handler,
+        message,
+        channel,
+    };
+    let result = if command.allowed_in(&ctx) {
+        command
+            .parse(arg_text, "!")
+            .and_then(|args| (command.run)(&ctx, &args))
+    } else {
+        Err(format!(
+            "!{} doesn't work in this channel, see !help",
+            command.name
+        ))
+    };
+
+    if let Err(msg) = result {
+        error!("{}", msg);
+        bot_say_to(&handler.workspace, channel, &msg);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_keep_spaces() {
+        let args = parse_args(r#"  one "two three"  “four five” it's "#, &[]).unwrap();
+        assert_eq!(
+            args.positional,
+            vec!["one", "two three", "four five", "it's"]
+        );
+    }
+
+    #[test]
+    fn unterminated_quotes() {
+        assert!(parse_args(r#"one "two three"#, &[]).is_err());
+        assert!(parse_args("'one", &[]).is_err());
+        assert!(parse_args("“one", &[]).is_err());
+    }
+
+    #[test]
+    fn value_flags() {
+        for text in &["--tag=rust more", "--tag rust more", "—tag rust more"] {
+            let args = parse_args(text, &["tag"]).unwrap();
+            assert_eq!(args.value("tag"), Some("rust"), "{}", text);
+            assert_eq!(args.positional, vec!["more"], "{}", text);
+        }
+        assert!(parse_args("--tag", &["tag"]).is_err());
+    }
+
+    #[test]
+    fn flags_without_values() {
+        // only value flags take the next argument
+        let args = parse_args("--all rust", &[]).unwrap();
+        assert!(args.flags.contains_key("all"));
+        assert_eq!(args.value("all"), None);
+        assert_eq!(args.positional, vec!["rust"]);
+
+        let args = parse_args("--all=yes", &[]).unwrap();
+        assert_eq!(args.value("all"), Some("yes"));
+        // a bare -- isn't a flag
+        assert_eq!(parse_args("--", &[]).unwrap().positional, vec!["--"]);
+    }
+
+    fn check(name: &str, text: &str) -> Result<(), String> {
+        Command::find(name).unwrap().parse(text, "!").map(|_| ())
+    }
+
+    #[test]
+    fn unknown_flags() {
+        assert!(check("last", "--tag rust").is_ok());
+        let err = check("last", "--tags").unwrap_err();
+        assert!(err.starts_with("unknown flag --tags"), "{}", err);
+        assert!(check("search", "rust --fast").is_err());
+    }
+
+    #[test]
+    fn arity() {
+        assert!(check("last", "").is_ok());
+        assert_eq!(
+            check("last", "five").unwrap_err(),
+            "usage: !last [--tag <tag>]"
+        );
+        assert!(check("put", "").is_err());
+        assert!(check("put", "https://example.com #a #b #c").is_ok());
+        assert!(check("tag", "1").is_err());
+        assert!(check("tag", "1 +rust").is_ok());
+        assert!(check("feed", "add https://example.com #general").is_ok());
+        assert!(check("feed", "add https://example.com #general extra").is_err());
+    }
+}
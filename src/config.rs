@@ -0,0 +1,436 @@
+//! feed subscriptions, loaded from a toml file at startup
+//!
+//! each workspace has its own file, see workspace. with a single workspace the file is read from
+//! the path in RUSTY_FEEDS_PATH (default: feeds.toml). it looks like:
+//!
+//! ```toml
+//! [[feed]]
+//! url = "https://this-week-in-rust.org/rss.xml"
+//! type = "rss"
+//! channel = "rust"
+//! ```
+use crate::digest::Schedule;
+use crate::filter::Filter;
+use crate::reader::{Feed, FeedType, Route, DEFAULT_INTERVAL, DEFAULT_RETAIN};
+use crate::SlackChannel;
+use failure::{bail, format_err, Error};
+use log::info;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::time::Duration;
+use url::Url;
+
+const FEEDS_PATH_ENV_VAR: &str = "RUSTY_FEEDS_PATH";
+const DEFAULT_FEEDS_PATH: &str = "feeds.toml";
+// don't let anyone hammer a feed host
+const MIN_INTERVAL_SECS: u64 = 60;
+
+// everything the feed loop needs from the subscription file
+#[derive(Debug, Clone)]
+pub struct Subscriptions {
+    pub feeds: Vec<Feed>,
+    // channels that get their articles as a digest
+    pub digests: Vec<(SlackChannel, Schedule)>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubscriptionFile {
+    #[serde(default, rename = "feed")]
+    feeds: Vec<FeedEntry>,
+    #[serde(default, rename = "digest")]
+    digests: Vec<DigestEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DigestEntry {
+    channel: String,
+    schedule: String,
+}
+
+// the type and channel are kept as plain strings here so that a bad value can be reported along
+// with the entry it came from, rather than as a bare toml parse error.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FeedEntry {
+    url: String,
+    name: Option<String>,
+    #[serde(rename = "type", default = "default_feed_type")]
+    feed_type: String,
+    // shorthand for a route to this channel with no filter of its own
+    channel: Option<String>,
+    #[serde(default, rename = "route")]
+    routes: Vec<RouteEntry>,
+    retain: Option<usize>,
+    // seconds between reads
+    interval: Option<u64>,
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+    #[serde(default)]
+    filter: FilterEntry,
+    // post this feed's articles as a digest on this schedule, whatever its channels do
+    digest: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RouteEntry {
+    channel: String,
+    #[serde(default)]
+    filter: FilterEntry,
+}
+
+// include/exclude rules for a feed or route, see filter::Filter for how they combine
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FilterEntry {
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(default)]
+    include_regex: Vec<String>,
+    #[serde(default)]
+    exclude_regex: Vec<String>,
+    #[serde(default)]
+    include_categories: Vec<String>,
+    #[serde(default)]
+    exclude_categories: Vec<String>,
+}
+
+impl FilterEntry {
+    fn build(&self) -> Result<Filter, regex::Error> {
+        Filter::new(
+            (&self.include, &self.exclude),
+            (&self.include_regex, &self.exclude_regex),
+            (&self.include_categories, &self.exclude_categories),
+        )
+    }
+}
+
+fn default_feed_type() -> String {
+    String::from("auto")
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+pub fn feeds_path() -> String {
+    std::env::var(FEEDS_PATH_ENV_VAR).unwrap_or_else(|_| String::from(DEFAULT_FEEDS_PATH))
+}
+
+impl Subscriptions {
+    // every channel something is posted to, so they can be checked before anything is posted
+    pub fn channels(&self) -> impl Iterator<Item = &SlackChannel> {
+        self.feeds
+            .iter()
+            .flat_map(|feed| feed.routes.iter().map(|route| &route.channel))
+            .chain(self.digests.iter().map(|(channel, _)| channel))
+    }
+}
+
+// read and validate the subscription file, skipping any feed marked `enabled = false`
+pub fn load_subscriptions(path: &str) -> Result<Subscriptions, Error> {
+    info!("loading feed subscriptions from {}", path);
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| format_err!("unable to read {}: {}", path, e))?;
+    parse_subscriptions(&contents).map_err(|e| format_err!("{}: {}", path, e))
+}
+
+fn parse_subscriptions(contents: &str) -> Result<Subscriptions, Error> {
+    let subscriptions: SubscriptionFile = toml::from_str(contents)?;
+
+    let mut digests: Vec<(SlackChannel, Schedule)> = Vec::new();
+    for (idx, entry) in subscriptions.digests.iter().enumerate() {
+        let name = format!("digest #{} ({})", idx + 1, entry.channel);
+        let channel: SlackChannel = entry
+            .channel
+            .parse()
+            .map_err(|e| format_err!("{}: {}", name, e))?;
+        if digests.iter().any(|(c, _)| *c == channel) {
+            bail!("{}: channel already has a digest", name);
+        }
+        let schedule: Schedule = entry
+            .schedule
+            .parse()
+            .map_err(|e| format_err!("{}: {}", name, e))?;
+        digests.push((channel, schedule));
+    }
+
+    let mut seen_urls = HashSet::new();
+    let mut feeds = Vec::new();
+    for (idx, entry) in subscriptions.feeds.iter().enumerate() {
+        // number entries from 1 so they line up with how a person would count [[feed]] blocks
+        let name = format!("feed #{} ({})", idx + 1, entry.url);
+
+        if let Err(e) = Url::parse(&entry.url) {
+            bail!("{}: invalid url: {}", name, e);
+        }
+        if !seen_urls.insert(entry.url.clone()) {
+            bail!("{}: duplicate url", name);
+        }
+        let feed_type: FeedType = entry
+            .feed_type
+            .parse()
+            .map_err(|e| format_err!("{}: {}", name, e))?;
+        let mut routes = Vec::new();
+        if let Some(channel) = &entry.channel {
+            let channel: SlackChannel = channel
+                .parse()
+                .map_err(|e| format_err!("{}: {}", name, e))?;
+            routes.push(Route::new(channel));
+        }
+        for (route_idx, route) in entry.routes.iter().enumerate() {
+            let route_name = format!("{}, route #{}", name, route_idx + 1);
+            let channel: SlackChannel = route
+                .channel
+                .parse()
+                .map_err(|e| format_err!("{}: {}", route_name, e))?;
+            let filter = route
+                .filter
+                .build()
+                .map_err(|e| format_err!("{}: invalid filter: {}", route_name, e))?;
+            routes.push(Route { channel, filter });
+        }
+        if routes.is_empty() {
+            bail!("{}: needs a channel or at least one route", name);
+        }
+        if entry.retain == Some(0) {
+            bail!("{}: retain must be greater than 0", name);
+        }
+        if let Some(interval) = entry.interval {
+            if interval < MIN_INTERVAL_SECS {
+                bail!(
+                    "{}: interval must be at least {} seconds",
+                    name,
+                    MIN_INTERVAL_SECS
+                );
+            }
+        }
+
+        let filter = entry
+            .filter
+            .build()
+            .map_err(|e| format_err!("{}: invalid filter: {}", name, e))?;
+        let digest = match &entry.digest {
+            Some(schedule) => Some(
+                schedule
+                    .parse::<Schedule>()
+                    .map_err(|e| format_err!("{}: invalid digest: {}", name, e))?,
+            ),
+            None => None,
+        };
+
+        if !entry.enabled {
+            info!("skipping disabled {}", name);
+            continue;
+        }
+
+        let mut feed = Feed::new(&entry.url, feed_type, routes[0].channel.clone());
+        feed.routes = routes;
+        feed.name = entry.name.clone();
+        feed.filter = filter;
+        feed.digest = digest;
+        feed.retain = entry.retain.unwrap_or(DEFAULT_RETAIN);
+        feed.interval = entry
+            .interval
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_INTERVAL);
+        feeds.push(feed);
+    }
+
+    Ok(Subscriptions { feeds, digests })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOOD: &str = r#"
+[[feed]]
+url = "https://example.com/feed.xml"
+channel = "rust"
+"#;
+
+    // the error for a file with a good feed and then this one
+    fn error(feed: &str) -> String {
+        let contents = format!("{}\n[[feed]]\n{}", GOOD, feed);
+        match parse_subscriptions(&contents) {
+            Ok(_) => panic!("parsed {}", feed),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn assert_error(feed: &str, url: &str, message: &str) {
+        let err = error(feed);
+        let prefix = format!("feed #2 ({}): ", url);
+        assert!(err.starts_with(&prefix), "{}", err);
+        assert!(err.contains(message), "{}", err);
+    }
+
+    #[test]
+    fn parse_feeds() {
+        let subscriptions = parse_subscriptions(&format!(
+            r#"{}
+[[feed]]
+url = "https://example.com/atom.xml"
+type = "atom"
+name = "example"
+retain = 50
+interval = 600
+digest = "daily 09:00"
+
+[[feed.route]]
+channel = "rust"
+filter = {{ include = ["rust"] }}
+
+[[feed.route]]
+channel = "general"
+
+[[feed]]
+url = "https://example.com/off.xml"
+channel = "rust"
+enabled = false
+
+[[digest]]
+channel = "general"
+schedule = "weekdays 17:00 America/Chicago"
+"#,
+            GOOD
+        ))
+        .unwrap();
+
+        assert_eq!(subscriptions.feeds.len(), 2);
+        let feed = &subscriptions.feeds[1];
+        assert_eq!(feed.name.as_deref(), Some("example"));
+        assert_eq!(feed.retain, 50);
+        assert_eq!(feed.interval, Duration::from_secs(600));
+        assert!(feed.digest.is_some());
+        let channels: Vec<&str> = feed.routes.iter().map(|r| r.channel.name()).collect();
+        assert_eq!(channels, vec!["rust", "general"]);
+        assert_eq!(subscriptions.feeds[0].retain, DEFAULT_RETAIN);
+        assert_eq!(subscriptions.feeds[0].interval, DEFAULT_INTERVAL);
+        assert_eq!(subscriptions.digests.len(), 1);
+    }
+
+    #[test]
+    fn bad_urls() {
+        assert_error(r#"url = "not a url""#, "not a url", "invalid url");
+        assert_error(
+            r#"url = "https://example.com/feed.xml"
+channel = "rust""#,
+            "https://example.com/feed.xml",
+            "duplicate url",
+        );
+    }
+
+    #[test]
+    fn bad_types_and_channels() {
+        let url = "https://example.com/other.xml";
+        assert_error(
+            &format!("url = \"{}\"\ntype = \"gopher\"\nchannel = \"rust\"", url),
+            url,
+            "gopher",
+        );
+        assert_error(&format!("url = \"{}\"", url), url, "needs a channel");
+        assert_error(
+            &format!("url = \"{}\"\nchannel = \"not a channel\"", url),
+            url,
+            "invalid channel name",
+        );
+        let err = error(&format!(
+            "url = \"{}\"\n[[feed.route]]\nchannel = \"#a.b\"",
+            url
+        ));
+        assert!(
+            err.starts_with(&format!("feed #2 ({}), route #1: ", url)),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn bad_numbers() {
+        let url = "https://example.com/other.xml";
+        assert_error(
+            &format!("url = \"{}\"\nchannel = \"rust\"\nretain = 0", url),
+            url,
+            "retain must be greater than 0",
+        );
+        assert_error(
+            &format!("url = \"{}\"\nchannel = \"rust\"\ninterval = 59", url),
+            url,
+            "interval must be at least 60 seconds",
+        );
+    }
+
+    #[test]
+    fn bad_filters_and_digests() {
+        let url = "https://example.com/other.xml";
+        assert_error(
+            &format!(
+                "url = \"{}\"\nchannel = \"rust\"\nfilter = {{ include_regex = [\"(\"] }}",
+                url
+            ),
+            url,
+            "invalid filter",
+        );
+        let err = error(&format!(
+            "url = \"{}\"\n[[feed.route]]\nchannel = \"rust\"\n\
+             filter = {{ exclude_regex = [\"[\"] }}",
+            url
+        ));
+        assert!(
+            err.starts_with(&format!("feed #2 ({}), route #1: invalid filter", url)),
+            "{}",
+            err
+        );
+        assert_error(
+            &format!(
+                "url = \"{}\"\nchannel = \"rust\"\ndigest = \"someday 09:00\"",
+                url
+            ),
+            url,
+            "invalid digest",
+        );
+    }
+
+    #[test]
+    fn disabled_feeds_are_still_checked() {
+        let url = "https://example.com/other.xml";
+        assert_error(
+            &format!("url = \"{}\"\nenabled = false\ninterval = 10", url),
+            url,
+            "needs a channel",
+        );
+        assert_error(
+            &format!(
+                "url = \"{}\"\nchannel = \"rust\"\nenabled = false\ninterval = 10",
+                url
+            ),
+            url,
+            "interval must be at least 60 seconds",
+        );
+    }
+
+    #[test]
+    fn bad_digests() {
+        let digest = |entry: &str| {
+            parse_subscriptions(&format!("{}\n[[digest]]\n{}", GOOD, entry))
+                .unwrap_err()
+                .to_string()
+        };
+        let err = digest("channel = \"general\"\nschedule = \"daily 9am\"");
+        assert!(err.starts_with("digest #1 (general): "), "{}", err);
+        let err = digest(
+            "channel = \"general\"\nschedule = \"daily 09:00\"\n\
+             [[digest]]\nchannel = \"general\"\nschedule = \"weekends 10:00\"",
+        );
+        assert!(
+            err.starts_with("digest #2 (general): channel already has a digest"),
+            "{}",
+            err
+        );
+    }
+}
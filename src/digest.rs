@@ -0,0 +1,414 @@
+//! digests, for posting a batch of articles at a set time instead of one message per article
+//!
+//! a digest can be set up for a feed or for a whole channel. a feed's own digest wins over its
+//! channel's, and anything without either is posted straight away.
+use crate::blocks::{digest_blocks, digest_text, link};
+use crate::reader::{Article, Feed};
+use crate::utils::post_blocks;
+use crate::workspace::Workspace;
+use crate::SlackChannel;
+use chrono::{DateTime, Datelike, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use failure::{bail, format_err, Error};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// when a digest goes out, written like "weekdays 09:00 America/Chicago". the days can be daily,
+// weekdays, weekends or a comma separated list like mon,thu. the timezone defaults to UTC.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    days: Vec<Weekday>,
+    time: NaiveTime,
+    tz: Tz,
+}
+
+fn parse_days(s: &str) -> Result<Vec<Weekday>, Error> {
+    use Weekday::*;
+    match s.to_lowercase().as_str() {
+        "daily" => Ok(vec![Mon, Tue, Wed, Thu, Fri, Sat, Sun]),
+        "weekdays" => Ok(vec![Mon, Tue, Wed, Thu, Fri]),
+        "weekends" => Ok(vec![Sat, Sun]),
+        list => list
+            .split(',')
+            .map(|day| {
+                day.trim()
+                    .parse::<Weekday>()
+                    .map_err(|_| format_err!("unknown day '{}'", day))
+            })
+            .collect(),
+    }
+}
+
+impl std::str::FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Schedule, Error> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        let (days, time, tz) = match parts.as_slice() {
+            [days, time] => (days, time, "UTC"),
+            [days, time, tz] => (days, time, *tz),
+            _ => bail!(
+                "expected a schedule like 'weekdays 09:00 America/Chicago', got '{}'",
+                s
+            ),
+        };
+
+        Ok(Schedule {
+            days: parse_days(days)?,
+            time: NaiveTime::parse_from_str(time, "%H:%M")
+                .map_err(|_| format_err!("expected a time like 09:00, got '{}'", time))?,
+            tz: tz
+                .parse()
+                .map_err(|_| format_err!("unknown timezone '{}'", tz))?,
+        })
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let days: Vec<String> = self
+            .days
+            .iter()
+            .map(|d| d.to_string().to_lowercase())
+            .collect();
+        write!(
+            f,
+            "{} {} {}",
+            days.join(","),
+            self.time.format("%H:%M"),
+            self.tz.name()
+        )
+    }
+}
+
+impl Schedule {
+    // the first time this schedule comes around after `now`
+    pub fn next_after(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let today = now.with_timezone(&self.tz).date_naive();
+        // a week and a day covers every schedule, even one that's already passed today
+        (0..=7)
+            .filter_map(|offset| today.checked_add_days(chrono::Days::new(offset)))
+            .filter(|date| self.days.contains(&date.weekday()))
+            // a time that doesn't exist because of a dst change is skipped for that day
+            .filter_map(|date| {
+                self.tz
+                    .from_local_datetime(&date.and_time(self.time))
+                    .earliest()
+            })
+            .map(|local| local.with_timezone(&Utc))
+            .find(|&when| when > now)
+            .unwrap_or_else(|| now + chrono::Duration::days(1))
+    }
+}
+
+// an article waiting in a digest. only what the digest message needs is kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DigestEntry {
+    pub feed_name: String,
+    pub title: String,
+    pub url: String,
+}
+
+// a digest as it's kept in the state file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedDigest {
+    next_run: DateTime<Utc>,
+    entries: Vec<DigestEntry>,
+}
+
+#[derive(Debug)]
+struct Digest {
+    channel: SlackChannel,
+    schedule: Schedule,
+    next_run: DateTime<Utc>,
+    entries: Vec<DigestEntry>,
+}
+
+impl Digest {
+    // digests are told apart by where and when they post, which is also how they're saved
+    fn key(&self) -> String {
+        digest_key(&self.channel, &self.schedule)
+    }
+}
+
+fn digest_key(channel: &SlackChannel, schedule: &Schedule) -> String {
+    format!("{} {}", channel.name(), schedule)
+}
+
+#[derive(Debug, Default)]
+pub struct Digests {
+    channel_schedules: Vec<(SlackChannel, Schedule)>,
+    pending: Vec<Digest>,
+}
+
+impl Digests {
+    pub fn new(channel_schedules: Vec<(SlackChannel, Schedule)>) -> Digests {
+        Digests {
+            channel_schedules,
+            pending: Vec::new(),
+        }
+    }
+
+    fn schedule_for(&self, feed: &Feed, channel: &SlackChannel) -> Option<Schedule> {
+        feed.digest.clone().or_else(|| {
+            self.channel_schedules
+                .iter()
+                .find(|(c, _)| c == channel)
+                .map(|(_, s)| s.clone())
+        })
+    }
+
+    // hold on to an article if it's meant for a digest. returns false if it should be posted now.
+    pub fn queue(&mut self, feed: &Feed, channel: &SlackChannel, article: &Article) -> bool {
+        let schedule = match self.schedule_for(feed, channel) {
+            Some(schedule) => schedule,
+            None => return false,
+        };
+
+        let entry = DigestEntry {
+            feed_name: feed.display_name(article),
+            title: article.title.to_string(),
+            url: article.url.to_string(),
+        };
+        info!(
+            "holding {} for the {} digest",
+            entry.title,
+            digest_key(channel, &schedule)
+        );
+        self.push(channel.clone(), schedule, entry);
+        true
+    }
+
+    fn push(&mut self, channel: SlackChannel, schedule: Schedule, entry: DigestEntry) {
+        self.digest_mut(channel, schedule).entries.push(entry);
+    }
+
+    // the digest for this channel and schedule, starting a new one if there isn't one yet
+    fn digest_mut(&mut self, channel: SlackChannel, schedule: Schedule) -> &mut Digest {
+        let key = digest_key(&channel, &schedule);
+        match self.pending.iter().position(|d| d.key() == key) {
+            Some(idx) => &mut self.pending[idx],
+            None => {
+                self.pending.push(Digest {
+                    next_run: schedule.next_after(Utc::now()),
+                    channel,
+                    schedule,
+                    entries: Vec::new(),
+                });
+                self.pending.last_mut().unwrap()
+            }
+        }
+    }
+
+    pub fn next_run(&self) -> Option<DateTime<Utc>> {
+        self.pending.iter().map(|d| d.next_run).min()
+    }
+
+    // post every digest whose time has come. if a digest can't be posted as one message, its
+    // articles are posted one at a time instead so nothing is lost.
+    pub fn post_due(&mut self, client: &reqwest::blocking::Client, workspace: &Workspace) {
+        let token = &workspace.token;
+        let now = Utc::now();
+        let (due, waiting): (Vec<Digest>, Vec<Digest>) =
+            self.pending.drain(..).partition(|d| d.next_run <= now);
+        self.pending = waiting;
+
+        for digest in due {
+            let chan_id = match workspace.channels.id(&digest.channel) {
+                Some(chan_id) => chan_id,
+                None => {
+                    error!(
+                        "no #{} channel, dropping {} digest with {} articles",
+                        digest.channel,
+                        digest.key(),
+                        digest.entries.len()
+                    );
+                    continue;
+                }
+            };
+            info!(
+                "posting {} digest with {} articles",
+                digest.key(),
+                digest.entries.len()
+            );
+            let text = digest_text(&digest.entries);
+            let blocks = digest_blocks(&digest.entries);
+            if let Err(e) = post_blocks(client, token, &chan_id, &text, &blocks) {
+                error!(
+                    "unable to post {} digest, posting articles individually: {}",
+                    digest.key(),
+                    e
+                );
+                for entry in &digest.entries {
+                    let text = link(&entry.url, &entry.title);
+                    let msg = slack_api::sync::chat::PostMessageRequest {
+                        channel: &chan_id,
+                        text: &text,
+                        as_user: Some(true),
+                        ..Default::default()
+                    };
+                    if let Err(e) = slack_api::sync::chat::post_message(client, token, &msg) {
+                        error!("unable to post {} to {}: {}", entry.url, chan_id, e);
+                    }
+                }
+            }
+        }
+    }
+
+    // what's waiting and when it's due, keyed by digest, for the state file
+    pub fn saved(&self) -> HashMap<String, SavedDigest> {
+        self.pending
+            .iter()
+            .map(|d| {
+                let saved = SavedDigest {
+                    next_run: d.next_run,
+                    entries: d.entries.clone(),
+                };
+                (d.key(), saved)
+            })
+            .collect()
+    }
+
+    // pick up whatever was waiting before a restart. the key carries the schedule, so a digest
+    // still goes out on the schedule it was queued for even if the config has since changed, and
+    // one that fell due while the bot was down goes out straight away.
+    pub fn restore(&mut self, saved: &HashMap<String, SavedDigest>) {
+        for (key, saved) in saved {
+            let SavedDigest { next_run, entries } = saved;
+            let restored = key.split_once(' ').and_then(|(channel, schedule)| {
+                Some((
+                    channel.parse::<SlackChannel>().ok()?,
+                    schedule.parse::<Schedule>().ok()?,
+                ))
+            });
+            match restored {
+                Some((channel, schedule)) => {
+                    info!(
+                        "restoring {} articles for the {} digest",
+                        entries.len(),
+                        key
+                    );
+                    let digest = self.digest_mut(channel, schedule);
+                    digest.entries.extend(entries.iter().cloned());
+                    if *next_run <= Utc::now() {
+                        info!(
+                            "the {} digest fell due at {}, posting it now",
+                            key, next_run
+                        );
+                    }
+                    digest.next_run = *next_run;
+                }
+                None => error!(
+                    "unable to restore digest {}, dropping {} articles",
+                    key,
+                    entries.len()
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_schedule() {
+        let schedule: Schedule = "weekdays 09:00 America/Chicago".parse().unwrap();
+        assert_eq!(
+            schedule.to_string(),
+            "mon,tue,wed,thu,fri 09:00 America/Chicago"
+        );
+        let schedule: Schedule = "Mon,thu 17:30".parse().unwrap();
+        assert_eq!(schedule.to_string(), "mon,thu 17:30 UTC");
+        // what's saved in the state file has to parse back to the same schedule
+        assert_eq!(
+            schedule
+                .to_string()
+                .parse::<Schedule>()
+                .unwrap()
+                .to_string(),
+            schedule.to_string()
+        );
+        assert_eq!("daily 00:00".parse::<Schedule>().unwrap().days.len(), 7);
+        assert_eq!(
+            "weekends 00:00".parse::<Schedule>().unwrap().days,
+            vec![Weekday::Sat, Weekday::Sun]
+        );
+    }
+
+    #[test]
+    fn parse_bad_schedules() {
+        for bad in &[
+            "",
+            "daily",
+            "daily 09:00 UTC extra",
+            "someday 09:00",
+            "mon,funday 09:00",
+            "daily 9am",
+            "daily 25:00",
+            "daily 09:00 Mars/Olympus_Mons",
+        ] {
+            assert!(bad.parse::<Schedule>().is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn next_after_across_dst() {
+        let schedule: Schedule = "daily 09:00 America/Chicago".parse().unwrap();
+        // 09:00 is 15:00 utc in cst and 14:00 utc in cdt
+        assert_eq!(
+            schedule.next_after(at("2020-03-07T16:00:00Z")),
+            at("2020-03-08T14:00:00Z")
+        );
+        assert_eq!(
+            schedule.next_after(at("2020-10-31T15:00:00Z")),
+            at("2020-11-01T15:00:00Z")
+        );
+
+        // 02:30 doesn't exist on the day clocks go forward, so that day is skipped
+        let schedule: Schedule = "daily 02:30 America/Chicago".parse().unwrap();
+        assert_eq!(
+            schedule.next_after(at("2020-03-07T09:00:00Z")),
+            at("2020-03-09T07:30:00Z")
+        );
+        // and 01:30 happens twice the day they go back, the first one wins
+        let schedule: Schedule = "daily 01:30 America/Chicago".parse().unwrap();
+        assert_eq!(
+            schedule.next_after(at("2020-10-31T12:00:00Z")),
+            at("2020-11-01T06:30:00Z")
+        );
+    }
+
+    fn entry() -> DigestEntry {
+        DigestEntry {
+            feed_name: String::from("feed"),
+            title: String::from("title"),
+            url: String::from("https://example.com/article"),
+        }
+    }
+
+    #[test]
+    fn restore_keeps_next_run() {
+        let due = at("2020-01-01T09:00:00Z");
+        let saved: HashMap<String, SavedDigest> = vec![(
+            String::from("general daily 09:00 UTC"),
+            SavedDigest {
+                next_run: due,
+                entries: vec![entry()],
+            },
+        )]
+        .into_iter()
+        .collect();
+
+        let mut digests = Digests::default();
+        digests.restore(&saved);
+        assert_eq!(digests.next_run(), Some(due));
+        assert_eq!(digests.pending[0].entries.len(), 1);
+    }
+}
@@ -0,0 +1,175 @@
+//! finding the feeds behind a plain website url
+//!
+//! looks for <link rel="alternate"> tags advertising a feed, and failing that tries the paths
+//! feeds usually live at.
+use crate::fetch::Fetcher;
+use crate::reader::{detect_feed, feed_from_body, sniff_feed_type, Feed, FeedUrl};
+use crate::utils::html_tags;
+use crate::SlackChannel;
+use failure::{format_err, Error};
+use log::{debug, info};
+use url::Url;
+
+const FEED_MIME_TYPES: [&str; 4] = [
+    "application/rss+xml",
+    "application/atom+xml",
+    "application/feed+json",
+    "application/rdf+xml",
+];
+
+// pages can advertise a feed for every category or tag, and each one is another request
+const MAX_ADVERTISED_FEEDS: usize = 5;
+
+const COMMON_FEED_PATHS: [&str; 7] = [
+    "/feed",
+    "/rss.xml",
+    "/index.xml",
+    "/atom.xml",
+    "/feed.xml",
+    "/feed.json",
+    "/rss",
+];
+
+// feed urls advertised by a page, resolved against the page's own url
+fn advertised_feeds(page_url: &Url, html: &str) -> Vec<Url> {
+    let mut urls: Vec<Url> = Vec::new();
+    for attrs in html_tags(html, "link") {
+        let get = |name: &str| {
+            attrs
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str())
+        };
+
+        let is_alternate = get("rel")
+            .map(|rel| {
+                rel.split_whitespace()
+                    .any(|r| r.eq_ignore_ascii_case("alternate"))
+            })
+            .unwrap_or(false);
+        let is_feed = get("type")
+            .map(|t| FEED_MIME_TYPES.contains(&t.trim().to_lowercase().as_str()))
+            .unwrap_or(false);
+
+        if let (true, true, Some(href)) = (is_alternate, is_feed, get("href")) {
+            if let Ok(url) = page_url.join(href.trim()) {
+                if !urls.contains(&url) {
+                    urls.push(url);
+                }
+            }
+        }
+    }
+    urls
+}
+
+fn probe(candidate: &Url, channel: &SlackChannel) -> Option<Feed> {
+    match detect_feed(candidate.as_str(), channel.clone()) {
+        Ok(feed) => Some(feed),
+        Err(e) => {
+            debug!("{} isn't a feed: {}", candidate, e);
+            None
+        }
+    }
+}
+
+// find the feeds for a url. if the url is already a feed that's the only candidate, otherwise
+// the feeds the page advertises are returned in the order they were found, up to
+// MAX_ADVERTISED_FEEDS of them. a page that doesn't advertise any gets the first of the common
+// paths that turns out to be a feed.
+pub fn discover_feeds(url: &Url, channel: SlackChannel) -> Result<Vec<Feed>, Error> {
+    let body = Fetcher::new()
+        .fetch(&FeedUrl::from_str(url.as_str()))?
+        .ok_or_else(|| format_err!("{} returned no content", url))?;
+
+    if sniff_feed_type(&body).is_some() {
+        return Ok(vec![feed_from_body(url.as_str(), &body, channel)?]);
+    }
+
+    let mut advertised = advertised_feeds(url, &body);
+    // the page itself isn't a feed, so there's no point fetching it again
+    advertised.retain(|candidate| candidate != url);
+    let feeds: Vec<Feed> = if advertised.is_empty() {
+        debug!("{} doesn't advertise any feeds, trying common paths", url);
+        // these are usually all the same feed under different names
+        COMMON_FEED_PATHS
+            .iter()
+            .filter_map(|path| url.join(path).ok())
+            .filter(|candidate| candidate != url)
+            .find_map(|candidate| probe(&candidate, &channel))
+            .into_iter()
+            .collect()
+    } else {
+        if advertised.len() > MAX_ADVERTISED_FEEDS {
+            info!(
+                "{} advertises {} feeds, only trying the first {}",
+                url,
+                advertised.len(),
+                MAX_ADVERTISED_FEEDS
+            );
+        }
+        advertised
+            .iter()
+            .take(MAX_ADVERTISED_FEEDS)
+            .filter_map(|candidate| probe(candidate, &channel))
+            .collect()
+    };
+
+    info!("discovered {} feeds from {}", feeds.len(), url);
+    if feeds.is_empty() {
+        Err(format_err!("couldn't find any feeds at {}", url))
+    } else {
+        Ok(feeds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn advertised(html: &str) -> Vec<String> {
+        let page = Url::parse("https://example.com/blog/post.html").unwrap();
+        advertised_feeds(&page, html)
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn relative_hrefs() {
+        let html = r#"<head>
+            <link rel="alternate" type="application/rss+xml" href="/feed.xml">
+            <link rel="alternate" type="application/atom+xml" href="atom.xml">
+            <link rel="alternate" type="application/feed+json" href="https://feeds.example.org/x">
+        </head>"#;
+        assert_eq!(
+            advertised(html),
+            vec![
+                "https://example.com/feed.xml",
+                "https://example.com/blog/atom.xml",
+                "https://feeds.example.org/x",
+            ]
+        );
+    }
+
+    #[test]
+    fn rel_and_type() {
+        let html = r#"
+            <link rel="alternate home" type="application/rss+xml" href="/one.xml">
+            <link REL="Alternate" type=" Application/Atom+XML " href="/two.xml">
+            <link rel="stylesheet" type="text/css" href="/style.css">
+            <link rel="alternate" type="text/html" href="/es/">
+            <link rel="alternate" hreflang="es" href="/es/">
+            <link rel="canonical" type="application/rss+xml" href="/not-a-feed.xml">
+            <link rel="alternate" type="application/rss+xml" href="/one.xml">
+        "#;
+        assert_eq!(
+            advertised(html),
+            vec!["https://example.com/one.xml", "https://example.com/two.xml"]
+        );
+    }
+
+    #[test]
+    fn nothing_advertised() {
+        assert!(advertised("<html><head><title>hi</title></head></html>").is_empty());
+    }
+}
@@ -0,0 +1,290 @@
+//! the events api, where slack posts events to us over http, and slash commands
+//!
+//! one server takes the requests for every workspace, events at /slack/events/<workspace name>
+//! and slash commands at /slack/commands/<workspace name>. it listens on the address in
+//! RUSTY_EVENTS_ADDR (default: 0.0.0.0:3000), and every request is checked against the
+//! workspace's signing secret before anything is done with it.
+use crate::slash::handle_command;
+use crate::Handler;
+use chrono::Utc;
+use failure::{format_err, Error};
+use hmac::{Hmac, Mac};
+use log::{debug, error, info};
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::io::Read;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Sender};
+use tiny_http::{Request, Response, Server};
+use url::form_urlencoded;
+
+const EVENTS_ADDR_ENV_VAR: &str = "RUSTY_EVENTS_ADDR";
+const DEFAULT_EVENTS_ADDR: &str = "0.0.0.0:3000";
+const EVENTS_PATH: &str = "/slack/events/";
+const COMMANDS_PATH: &str = "/slack/commands/";
+// requests older than this are turned away, so one that's been captured can't be replayed
+const MAX_REQUEST_AGE_SECS: i64 = 5 * 60;
+// slack's payloads are small, anything bigger than this isn't from slack
+const MAX_BODY_BYTES: u64 = 1024 * 1024;
+
+// check a request's X-Slack-Signature, which is an hmac of the timestamp and body keyed with the
+// signing secret. see https://api.slack.com/authentication/verifying-requests-from-slack
+pub fn verify_signature(secret: &str, timestamp: &str, body: &str, signature: &str) -> bool {
+    verify_signature_at(secret, timestamp, body, signature, Utc::now().timestamp())
+}
+
+// the same, for a request checked at `now`
+fn verify_signature_at(
+    secret: &str,
+    timestamp: &str,
+    body: &str,
+    signature: &str,
+    now: i64,
+) -> bool {
+    let sent_at: i64 = match timestamp.parse() {
+        Ok(sent_at) => sent_at,
+        Err(_) => return false,
+    };
+    if (now - sent_at).abs() > MAX_REQUEST_AGE_SECS {
+        return false;
+    }
+
+    let expected = match signature
+        .strip_prefix("v0=")
+        .and_then(|hex_digest| hex::decode(hex_digest).ok())
+    {
+        Some(expected) => expected,
+        None => return false,
+    };
+    let mut mac = match Hmac::<Sha256>::new_varkey(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.input(format!("v0:{}:{}", timestamp, body).as_bytes());
+    // verify compares in constant time
+    mac.verify(&expected).is_ok()
+}
+
+pub fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+// read a request's body and make sure it was signed with `secret`
+pub fn read_signed_body(request: &mut Request, secret: &str) -> Result<String, Error> {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_BYTES)
+        .read_to_string(&mut body)?;
+
+    let timestamp = header(request, "X-Slack-Request-Timestamp").unwrap_or_default();
+    let signature = header(request, "X-Slack-Signature").unwrap_or_default();
+    if !verify_signature(secret, timestamp, &body, signature) {
+        return Err(format_err!("bad signature"));
+    }
+    Ok(body)
+}
+
+// what's left to do once slack has had its answer
+#[derive(Debug)]
+enum Work {
+    Event(Value),
+    Command(HashMap<String, String>),
+}
+
+// work out what to say back to slack, and what the handler for the workspace should do next
+fn route(
+    request: &mut Request,
+    handlers: &[Handler],
+    events: bool,
+) -> (u16, String, Option<(usize, Work)>) {
+    let url = request.url().to_string();
+    let (is_event, name) = match (
+        url.strip_prefix(EVENTS_PATH),
+        url.strip_prefix(COMMANDS_PATH),
+    ) {
+        (Some(name), _) if events => (true, name),
+        (_, Some(name)) => (false, name),
+        _ => return (404, String::from("not found"), None),
+    };
+    let idx = match handlers.iter().position(|h| h.workspace.name == name) {
+        Some(idx) => idx,
+        None => return (404, String::from("no such workspace"), None),
+    };
+    let secret = handlers[idx]
+        .workspace
+        .signing_secret
+        .clone()
+        .unwrap_or_default();
+
+    let body = match read_signed_body(request, &secret) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("rejecting request for {}: {}", name, e);
+            return (401, String::from("unauthorized"), None);
+        }
+    };
+    // we answer straight away, so a retry means slack lost our answer and we've already seen it
+    if header(request, "X-Slack-Retry-Num").is_some() {
+        debug!("ignoring a retry for {}", name);
+        return (200, String::new(), None);
+    }
+
+    // slash commands are form encoded, the answer is sent to the command's response_url later
+    if !is_event {
+        let form: HashMap<String, String> = form_urlencoded::parse(body.as_bytes())
+            .into_owned()
+            .collect();
+        return (200, String::new(), Some((idx, Work::Command(form))));
+    }
+
+    let payload: Value = match serde_json::from_str(&body) {
+        Ok(payload) => payload,
+        Err(e) => return (400, format!("invalid json: {}", e), None),
+    };
+    debug!("{:?}", payload);
+    match payload["type"].as_str() {
+        // slack checks the url is ours when it's first set up
+        Some("url_verification") => (
+            200,
+            payload["challenge"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            None,
+        ),
+        Some("event_callback") => (
+            200,
+            String::new(),
+            Some((idx, Work::Event(payload["event"].clone()))),
+        ),
+        _ => (200, String::new(), None),
+    }
+}
+
+// handle a workspace's events and commands in order on a thread of its own, so that a slow command
+// in one workspace doesn't hold up the others. the thread stops along with the server.
+fn spawn_worker(mut handler: Handler) -> Sender<Work> {
+    let (work_tx, work_rx) = channel();
+    std::thread::spawn(move || {
+        for work in work_rx {
+            let handled = catch_unwind(AssertUnwindSafe(|| match &work {
+                Work::Event(event) => handler.handle_event(event),
+                Work::Command(form) => handle_command(&handler, form),
+            }));
+            if handled.is_err() {
+                error!("{} panicked handling {:?}", handler.workspace.name, work);
+            }
+        }
+    });
+    work_tx
+}
+
+// take requests for every workspace until the server can't be started. without `events` only
+// slash commands are taken, for when messages come in some other way.
+pub fn serve(handlers: &mut [Handler], events: bool) -> Result<(), Error> {
+    let addr =
+        std::env::var(EVENTS_ADDR_ENV_VAR).unwrap_or_else(|_| String::from(DEFAULT_EVENTS_ADDR));
+    let server =
+        Server::http(&addr).map_err(|e| format_err!("unable to listen on {}: {}", addr, e))?;
+    info!("listening for slack on {}", addr);
+
+    for handler in handlers.iter_mut() {
+        handler.start_feeds();
+    }
+    let workers: Vec<Sender<Work>> = handlers
+        .iter()
+        .map(|handler| spawn_worker(handler.commands_only()))
+        .collect();
+
+    for mut request in server.incoming_requests() {
+        let (status, body, work) = route(&mut request, handlers, events);
+        // answer before handling, since some commands take longer than slack will wait
+        let response = Response::from_string(body).with_status_code(status);
+        if let Err(e) = request.respond(response) {
+            error!("unable to answer slack: {}", e);
+        }
+        if let Some((idx, work)) = work {
+            if workers[idx].send(work).is_err() {
+                error!(
+                    "the worker for {} has stopped",
+                    handlers[idx].workspace.name
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the example from https://api.slack.com/authentication/verifying-requests-from-slack
+    const SECRET: &str = "8f742231b10e8888abcd99yyyzzz85a5";
+    const TIMESTAMP: &str = "1531420618";
+    const BODY: &str = "token=xyzz0WbapA4vBCDEFasx0q6G&team_id=T1DC2JH3J&team_domain=testteamnow\
+        &channel_id=G8PSS9T3V&channel_name=foobar&user_id=U2CERLKJA&user_name=roadrunner\
+        &command=%2Fwebhook-collect&text=&response_url=https%3A%2F%2Fhooks.slack.com%2Fcommands\
+        %2FT1DC2JH3J%2F397700885554%2F96rGlfmibIGlgcZRskXaIFfN\
+        &trigger_id=398738663015.47445629121.803a0bc887a14d10d2c447fce8b6703c";
+    const SIGNATURE: &str = "v0=a2114d57b48eac39b9ad189dd8316235a7b4a8d21a10bd27519666489c69b503";
+    const SENT_AT: i64 = 1531420618;
+
+    #[test]
+    fn slack_example() {
+        assert!(verify_signature_at(
+            SECRET,
+            TIMESTAMP,
+            BODY,
+            SIGNATURE,
+            SENT_AT + 10
+        ));
+        assert!(!verify_signature_at(
+            "wrong secret",
+            TIMESTAMP,
+            BODY,
+            SIGNATURE,
+            SENT_AT
+        ));
+        assert!(!verify_signature_at(
+            SECRET, TIMESTAMP, BODY, "a2114d57", SENT_AT
+        ));
+    }
+
+    #[test]
+    fn stale_timestamp() {
+        let too_late = SENT_AT + MAX_REQUEST_AGE_SECS + 1;
+        assert!(!verify_signature_at(
+            SECRET, TIMESTAMP, BODY, SIGNATURE, too_late
+        ));
+        // clocks can be off either way
+        let too_early = SENT_AT - MAX_REQUEST_AGE_SECS - 1;
+        assert!(!verify_signature_at(
+            SECRET, TIMESTAMP, BODY, SIGNATURE, too_early
+        ));
+        // and the real clock is long past the example
+        assert!(!verify_signature(SECRET, TIMESTAMP, BODY, SIGNATURE));
+    }
+
+    #[test]
+    fn tampered_body() {
+        let tampered = BODY.replace("user_name=roadrunner", "user_name=coyote");
+        assert!(!verify_signature_at(
+            SECRET, TIMESTAMP, &tampered, SIGNATURE, SENT_AT
+        ));
+        // the timestamp is signed too
+        assert!(!verify_signature_at(
+            SECRET,
+            "1531420619",
+            BODY,
+            SIGNATURE,
+            SENT_AT
+        ));
+    }
+}
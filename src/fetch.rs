@@ -0,0 +1,207 @@
+//! shared http fetching for feeds
+//!
+//! remembers the ETag and Last-Modified headers from each response so the next request for the
+//! same feed can be made conditional. a 304 comes back as Ok(None) and costs us no download. the
+//! headers are only remembered once the body has been read successfully, otherwise a broken feed
+//! would get a 304 the next time and look like it had been fixed.
+use crate::reader::FeedUrl;
+use failure::Error;
+use log::debug;
+use reqwest::blocking::Client;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Debug, Default, Clone)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Fetcher {
+    client: Client,
+    validators: Mutex<HashMap<FeedUrl, Validators>>,
+}
+
+impl Fetcher {
+    pub fn new() -> Fetcher {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .user_agent(concat!("rusty-slackbot/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .unwrap();
+        Fetcher {
+            client,
+            validators: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // fetch the body of a feed, or None if it hasn't changed since we last fetched it
+    pub fn fetch(&self, url: &FeedUrl) -> Result<Option<String>, Error> {
+        self.fetch_with(url, |body| Ok(String::from(body)))
+    }
+
+    // fetch a feed and read its body with `parse`, or None if it hasn't changed since we last
+    // fetched it. if `parse` fails the next fetch downloads the feed in full again.
+    pub fn fetch_with<T>(
+        &self,
+        url: &FeedUrl,
+        parse: impl FnOnce(&str) -> Result<T, Error>,
+    ) -> Result<Option<T>, Error> {
+        let previous = self
+            .validators
+            .lock()
+            .unwrap()
+            .get(url)
+            .cloned()
+            .unwrap_or_default();
+
+        let mut request = self.client.get(&url.to_string());
+        if let Some(etag) = &previous.etag {
+            request = request.header(IF_NONE_MATCH, etag.as_str());
+        }
+        if let Some(last_modified) = &previous.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+        }
+
+        let res = request.send()?;
+        if res.status() == StatusCode::NOT_MODIFIED {
+            debug!("{} not modified", url);
+            return Ok(None);
+        }
+        let res = res.error_for_status()?;
+
+        let header = |name| {
+            res.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        };
+        let validators = Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        debug!("{} validators: {:?}", url, validators);
+
+        let body = res.text()?;
+        let parsed = parse(&body)?;
+        self.validators
+            .lock()
+            .unwrap()
+            .insert(url.clone(), validators);
+        Ok(Some(parsed))
+    }
+
+    // drop what we know about a feed so the next fetch downloads it in full
+    pub fn forget(&self, url: &FeedUrl) {
+        self.validators.lock().unwrap().remove(url);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use failure::format_err;
+    use std::sync::mpsc::{channel, Receiver};
+    use tiny_http::{Header, Response, Server};
+
+    // a status, headers and body to answer a request with
+    type Reply = (u16, &'static [(&'static str, &'static str)], &'static str);
+
+    // answer requests on a local port with `replies` in order, passing on each request's
+    // conditional headers
+    fn serve(replies: Vec<Reply>) -> (FeedUrl, Receiver<Validators>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = FeedUrl::from_str(&format!("http://{}/feed.xml", server.server_addr()));
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            for (status, headers, body) in replies {
+                let request = server.recv().unwrap();
+                let header = |name: &'static str| {
+                    request
+                        .headers()
+                        .iter()
+                        .find(|h| h.field.equiv(name))
+                        .map(|h| h.value.to_string())
+                };
+                tx.send(Validators {
+                    etag: header("If-None-Match"),
+                    last_modified: header("If-Modified-Since"),
+                })
+                .unwrap();
+                let mut response = Response::from_string(body).with_status_code(status);
+                for (name, value) in headers {
+                    response.add_header(Header::from_bytes(*name, *value).unwrap());
+                }
+                request.respond(response).unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    const VALIDATORS: &[(&str, &str)] = &[
+        ("ETag", "\"abc\""),
+        ("Last-Modified", "Wed, 01 Jan 2020 00:00:00 GMT"),
+    ];
+
+    #[test]
+    fn conditional_requests() {
+        let (url, requests) = serve(vec![(200, VALIDATORS, "feed"), (304, &[], "")]);
+        let fetcher = Fetcher::new();
+
+        assert_eq!(fetcher.fetch(&url).unwrap().as_deref(), Some("feed"));
+        let first = requests.recv().unwrap();
+        assert!(first.etag.is_none() && first.last_modified.is_none());
+
+        assert_eq!(fetcher.fetch(&url).unwrap(), None);
+        let second = requests.recv().unwrap();
+        assert_eq!(second.etag.as_deref(), Some("\"abc\""));
+        assert_eq!(
+            second.last_modified.as_deref(),
+            Some("Wed, 01 Jan 2020 00:00:00 GMT")
+        );
+    }
+
+    #[test]
+    fn validators_kept_only_after_parsing() {
+        let (url, requests) = serve(vec![(200, VALIDATORS, "broken"), (200, &[], "fixed")]);
+        let fetcher = Fetcher::new();
+
+        let parse = |body: &str| -> Result<String, Error> {
+            match body {
+                "broken" => Err(format_err!("unable to parse")),
+                body => Ok(String::from(body)),
+            }
+        };
+        assert!(fetcher.fetch_with(&url, parse).is_err());
+        requests.recv().unwrap();
+
+        // the broken body isn't cached, so it's downloaded again rather than getting a 304
+        assert_eq!(
+            fetcher.fetch_with(&url, parse).unwrap().as_deref(),
+            Some("fixed")
+        );
+        assert!(requests.recv().unwrap().etag.is_none());
+    }
+
+    #[test]
+    fn forget() {
+        let (url, requests) = serve(vec![(200, VALIDATORS, "feed"), (200, &[], "feed")]);
+        let fetcher = Fetcher::new();
+
+        fetcher.fetch(&url).unwrap();
+        requests.recv().unwrap();
+        fetcher.forget(&url);
+        fetcher.fetch(&url).unwrap();
+        assert!(requests.recv().unwrap().etag.is_none());
+    }
+
+    #[test]
+    fn errors() {
+        let (url, _requests) = serve(vec![(500, VALIDATORS, "oops")]);
+        assert!(Fetcher::new().fetch(&url).is_err());
+    }
+}
@@ -0,0 +1,132 @@
+//! include/exclude rules deciding which of a feed's articles get posted
+use crate::reader::Article;
+use crate::utils::strip_html;
+use regex::Regex;
+
+// an article is dropped if it matches any exclude rule. if there are include rules it also has to
+// match at least one of them, otherwise everything not excluded gets through.
+//
+// keywords and patterns are checked against the title and summary, categories against the
+// article's category tags. keywords and categories ignore case.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    include_categories: Vec<String>,
+    exclude_categories: Vec<String>,
+}
+
+// a keyword only matches whole words, so "ecs" doesn't let through every post about "decisions".
+// there's no word boundary next to punctuation, so keywords like "c++" or ".net" are only bounded
+// on the sides that start or end with a word character.
+fn keyword_regex(keyword: &str) -> Result<Regex, regex::Error> {
+    let keyword = keyword.trim();
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let start = if is_word(keyword.chars().next()) {
+        r"\b"
+    } else {
+        ""
+    };
+    let end = if is_word(keyword.chars().last()) {
+        r"\b"
+    } else {
+        ""
+    };
+    Regex::new(&format!(r"(?i){}{}{}", start, regex::escape(keyword), end))
+}
+
+fn compile(keywords: &[String], patterns: &[String]) -> Result<Vec<Regex>, regex::Error> {
+    keywords
+        .iter()
+        .map(|k| keyword_regex(k))
+        .chain(patterns.iter().map(|p| Regex::new(p)))
+        .collect()
+}
+
+fn lowercase(v: &[String]) -> Vec<String> {
+    v.iter().map(|s| s.trim().to_lowercase()).collect()
+}
+
+impl Filter {
+    // each pair is (include, exclude)
+    pub fn new(
+        keywords: (&[String], &[String]),
+        patterns: (&[String], &[String]),
+        categories: (&[String], &[String]),
+    ) -> Result<Filter, regex::Error> {
+        Ok(Filter {
+            include: compile(keywords.0, patterns.0)?,
+            exclude: compile(keywords.1, patterns.1)?,
+            include_categories: lowercase(categories.0),
+            exclude_categories: lowercase(categories.1),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty()
+            && self.exclude.is_empty()
+            && self.include_categories.is_empty()
+            && self.exclude_categories.is_empty()
+    }
+
+    pub fn matches(&self, article: &Article) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+
+        let title = article.title.to_string();
+        let summary = article
+            .summary
+            .as_deref()
+            .map(strip_html)
+            .unwrap_or_default();
+        let categories: Vec<String> = article
+            .categories
+            .iter()
+            .map(|c| c.trim().to_lowercase())
+            .collect();
+
+        let text_matches = |re: &Regex| re.is_match(&title) || re.is_match(&summary);
+        let in_categories = |c: &String| categories.contains(c);
+
+        if self.exclude.iter().any(text_matches)
+            || self.exclude_categories.iter().any(in_categories)
+        {
+            return false;
+        }
+
+        if self.include.is_empty() && self.include_categories.is_empty() {
+            return true;
+        }
+        self.include.iter().any(text_matches) || self.include_categories.iter().any(in_categories)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keywords_match_whole_words() {
+        let re = keyword_regex("ecs").unwrap();
+        assert!(re.is_match("running on ECS"));
+        assert!(re.is_match("ecs: a primer"));
+        assert!(!re.is_match("decisions, decisions"));
+    }
+
+    #[test]
+    fn keywords_with_punctuation_at_either_end() {
+        let re = keyword_regex("c++").unwrap();
+        assert!(re.is_match("what's new in C++20"));
+        assert!(re.is_match("c++"));
+        assert!(!re.is_match("abc++"));
+
+        let re = keyword_regex(".net").unwrap();
+        assert!(re.is_match("announcing .NET 5"));
+        assert!(!re.is_match(".network"));
+
+        let re = keyword_regex("node.js?").unwrap();
+        assert!(re.is_match("is node.js? worth it"));
+        assert!(!re.is_match("node.js is"));
+    }
+}
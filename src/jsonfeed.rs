@@ -0,0 +1,75 @@
+//! a minimal JSON Feed (https://jsonfeed.org/version/1.1) model, just the parts reader needs
+use serde::Deserialize;
+use serde_json::Value;
+
+const VERSION_PREFIX: &str = "https://jsonfeed.org/version/";
+
+#[derive(Debug, Deserialize)]
+pub struct JsonFeed {
+    pub version: String,
+    pub title: Option<String>,
+    #[serde(default)]
+    pub items: Vec<JsonItem>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JsonItem {
+    // the spec says this is a string, but some publishers send numbers
+    pub id: Option<Value>,
+    pub url: Option<String>,
+    pub external_url: Option<String>,
+    pub title: Option<String>,
+    pub summary: Option<String>,
+    pub content_text: Option<String>,
+    pub content_html: Option<String>,
+    pub date_published: Option<String>,
+    pub date_modified: Option<String>,
+    // 1.1 has a list of authors, 1.0 had just the one
+    #[serde(default)]
+    pub authors: Vec<JsonAuthor>,
+    pub author: Option<JsonAuthor>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JsonAuthor {
+    pub name: Option<String>,
+}
+
+impl JsonItem {
+    pub fn author(&self) -> Option<&str> {
+        self.authors
+            .first()
+            .or(self.author.as_ref())
+            .and_then(|a| a.name.as_deref())
+    }
+
+    pub fn id(&self) -> Option<String> {
+        match &self.id {
+            Some(Value::String(s)) if !s.is_empty() => Some(s.clone()),
+            Some(Value::Number(n)) => Some(n.to_string()),
+            _ => None,
+        }
+    }
+
+    // the item's own page if it has one, otherwise whatever it links out to
+    pub fn link(&self) -> Option<&str> {
+        [&self.url, &self.external_url]
+            .iter()
+            .filter_map(|url| url.as_deref())
+            .find(|url| !url.is_empty())
+    }
+}
+
+impl std::str::FromStr for JsonFeed {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<JsonFeed, failure::Error> {
+        let feed: JsonFeed = serde_json::from_str(s)?;
+        if !feed.version.starts_with(VERSION_PREFIX) {
+            failure::bail!("unsupported json feed version: {}", feed.version);
+        }
+        Ok(feed)
+    }
+}
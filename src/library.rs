@@ -1,209 +1,281 @@
-//! functions for use in #library
-use crate::utils::{add_reaction, bot_say, get_user_handle, get_user_real_name};
-use crate::SlackChannel;
+//! functions for use in #library, and for /library anywhere
+use crate::library_store::{NewRecord, Record};
+use crate::metadata::fetch_metadata;
+use crate::utils::{add_reaction, get_user_handle, get_user_real_name, parse_slack_url};
+use crate::workspace::Workspace;
 use chrono::offset::TimeZone;
-use chrono::{DateTime, Utc};
-use log::{debug, error, info};
-use prettytable::{format, Cell, Row, Table};
-use rusoto_core::Region;
-use rusoto_dynamodb::{AttributeValue, DynamoDb, DynamoDbClient, PutItemInput, QueryInput};
+use chrono::Utc;
+use failure::Error;
+use log::{debug, info, warn};
+use prettytable::{format, Table};
 use slack_api::{reactions::AddRequest, MessageStandard, Timestamp};
-use std::collections::HashMap;
 use url::Url;
 
-fn parse_slack_url(url: &str) -> &str {
-    info!("parser got url: {}", url);
-    if url.len() == 1 {
-        return url;
-    }
+// the longest a title gets in a table before it's cut short
+const MAX_TITLE_CHARS: usize = 60;
+const MAX_TAG_CHARS: usize = 32;
 
-    if url.contains('|') {
-        // when slack gets a bare url like example.com it attempts to present it as a full link
-        // in the UI and modifies it to slacks syntax for a link. for example:
-        // example.com becomes <http://example.com|example.com>
-        let url_string_parts: Vec<&str> = url.split('|').collect();
-        let url_string = url_string_parts[0];
+// a tag as it was typed, with or without its #, made lowercase. slack turns #name into a link
+// when there's a channel with that name, so those count as the name too.
+pub fn parse_tag(input: &str) -> Result<String, String> {
+    let tag = match input.strip_prefix("<#") {
+        Some(link) => link
+            .trim_end_matches('>')
+            .split_once('|')
+            .map(|(_, name)| name)
+            .unwrap_or_default(),
+        None => input.trim_start_matches('#'),
+    };
+    let tag = tag.to_lowercase();
+    let allowed = |c: char| c.is_alphanumeric() || "-_.+".contains(c);
+    if tag.is_empty() || tag.chars().count() > MAX_TAG_CHARS || !tag.chars().all(allowed) {
+        return Err(format!(
+            "{} isn't a tag, tags are up to {} letters, numbers, and - _ . +",
+            input, MAX_TAG_CHARS
+        ));
+    }
+    Ok(tag)
+}
 
-        // ignore the leading angle bracket
-        &url_string[1..]
-    } else {
-        // a url sent to slack as http://example.com will look like <http://example.com> by the time
-        // the bot sees it. We'll keep everything except the leading and trailing angle brackets.
-        &url[1..url.len() - 1]
+// the tags after the url in !put, which all start with #
+pub fn parse_put_tags(words: &[String]) -> Result<Vec<String>, String> {
+    let mut tags: Vec<String> = Vec::new();
+    for word in words {
+        if !word.starts_with('#') && !word.starts_with("<#") {
+            return Err(format!("tags start with a #, like #{}", word));
+        }
+        let tag = parse_tag(word)?;
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
     }
+    Ok(tags)
 }
 
-// put a record of who put which url into a DB.
-fn put_url(
-    url: &str,
-    user: &str,
-) -> Result<rusoto_dynamodb::PutItemOutput, rusoto_core::RusotoError<rusoto_dynamodb::PutItemError>>
-{
-    info!("got request to put record for user: {}, url: {}", user, url);
-    let client = DynamoDbClient::new(Region::UsEast1);
+fn tags_text(tags: &[String]) -> String {
+    let tags: Vec<String> = tags.iter().map(|tag| format!("#{}", tag)).collect();
+    tags.join(" ")
+}
 
-    let mut item: HashMap<String, AttributeValue> = HashMap::new();
-    let url_value = AttributeValue {
-        s: Some(url.to_string()),
-        ..Default::default()
+// put a record of who put which url into the workspace's library, along with what the page says
+// about itself
+fn put_url(url: &Url, tags: &[String], user: &str, workspace: &Workspace) -> Result<Record, Error> {
+    info!("got request to put record for user: {}, url: {}", user, url);
+    // a page we can't read is still worth saving
+    let metadata = fetch_metadata(url).unwrap_or_else(|e| {
+        warn!("unable to read metadata for {}: {}", url, e);
+        Default::default()
+    });
+    debug!("{} metadata: {:?}", url, metadata);
+    let record = NewRecord {
+        url: url.to_string(),
+        timestamp: Utc::now().timestamp(),
+        user: user.to_string(),
+        real_name: get_user_real_name(&workspace.token, user).unwrap_or_default(),
+        handle: get_user_handle(&workspace.token, user).unwrap_or_default(),
+        metadata,
+        tags: tags.to_vec(),
     };
+    let res = workspace.library.put(record);
+    debug!("{:?}", res);
+    res
+}
 
-    let partition_key_value = AttributeValue {
-        s: Some(String::from("records")),
-        ..Default::default()
-    };
+fn parse_url(input_string: &str) -> Result<Url, String> {
+    let url_string = parse_slack_url(input_string);
+    let parsed_url =
+        Url::parse(url_string).map_err(|_| format!("unable to parse as url: {}", input_string))?;
+    info!("success! parsed {} as url: {}", url_string, parsed_url);
+    Ok(parsed_url)
+}
 
-    let utc: DateTime<Utc> = Utc::now();
-    let timestamp = utc.timestamp().to_string();
-    let timestamp_attr_value = AttributeValue {
-        s: Some(timestamp),
-        ..Default::default()
-    };
+// save a url for a user, answering with what to tell them
+pub fn put(
+    input_string: &str,
+    tags: &[String],
+    user: &str,
+    workspace: &Workspace,
+) -> Result<String, String> {
+    let parsed_url = parse_url(input_string)?;
+    put_url(&parsed_url, tags, user, workspace)
+        .map(|record| format!("saved {} as {}", parsed_url, record.id))
+        .map_err(|e| format!("unable to save {}: {}", parsed_url, e))
+}
 
-    let user_val = AttributeValue {
-        s: Some(user.to_string()),
-        ..Default::default()
-    };
+// save the url from a !put message, reacting to the message to say whether it worked
+pub fn put_with_reaction(
+    input_string: &str,
+    tags: &[String],
+    message: &MessageStandard,
+    workspace: &Workspace,
+) -> Result<(), String> {
+    let timestamp: Timestamp = message.ts.unwrap();
+    let user: &str = message.user.as_deref().unwrap();
+    let channel: &str = message.channel.as_deref().unwrap();
 
-    let user_real_name_val = AttributeValue {
-        s: Some(get_user_real_name(user).unwrap_or_default()),
+    let parsed_url = parse_url(input_string)?;
+    let add_request = AddRequest {
+        channel: Some(channel),
+        timestamp: Some(timestamp),
+        name: if put_url(&parsed_url, tags, user, workspace).is_ok() {
+            "heavy_check_mark"
+        } else {
+            "x"
+        },
         ..Default::default()
     };
+    add_reaction(&workspace.token, add_request);
+    Ok(())
+}
 
-    let user_handle = AttributeValue {
-        s: Some(get_user_handle(user).unwrap_or_default()),
-        ..Default::default()
-    };
+fn records_table(records: &[Record]) -> String {
+    if records.is_empty() {
+        return String::from("no records found!");
+    }
 
-    item.insert(String::from("partition_key"), partition_key_value);
-    item.insert(String::from("url"), url_value);
-    item.insert(String::from("timestamp"), timestamp_attr_value);
-    item.insert(String::from("user"), user_val);
-    item.insert(String::from("real_name"), user_real_name_val);
-    item.insert(String::from("handle"), user_handle);
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(row!["id", "user", "timestamp", "link", "tags"]);
+    for record in records {
+        // the name lookup can fail, the handle is better than nothing then
+        let name = if record.real_name.is_empty() {
+            &record.handle
+        } else {
+            &record.real_name
+        };
+        let dt = format!("{}", Utc.timestamp_opt(record.timestamp, 0).unwrap());
+        // the title says more than the url, when the page had one
+        let link = match &record.metadata.title {
+            Some(title) if title.chars().count() > MAX_TITLE_CHARS => {
+                let short: String = title.chars().take(MAX_TITLE_CHARS - 1).collect();
+                format!("{}…", short)
+            }
+            Some(title) => title.clone(),
+            None => record.url.clone(),
+        };
+        table.add_row(row![record.id, name, dt, link, tags_text(&record.tags)]);
+    }
+    table.to_string()
+}
 
-    let put_item_input = PutItemInput {
-        table_name: String::from("library"),
-        item,
-        ..Default::default()
+// the five most recent entries, or the five most recent with the tag, as a table
+pub fn last_five_table(tag: Option<&str>, workspace: &Workspace) -> String {
+    let records = match tag {
+        Some(tag) => workspace.library.query_tag(tag, 5),
+        None => workspace.library.query(Some(5)),
     };
-
-    let res = client.put_item(put_item_input).sync();
-    debug!("{:?}", res);
-    res
+    match records {
+        Ok(records) => records_table(&records),
+        Err(e) => format!("unable to read the library: {}", e),
+    }
 }
 
-// take the string we get from slack and parse it so we can do actual work with it
-pub fn parse_put(message: MessageStandard) {
-    // expected input is like: !put <url>
-    let timestamp: Timestamp = message.ts.unwrap();
-    let text: String = message.text.unwrap();
-    let user: String = message.user.unwrap();
-    let channel: String = message.channel.unwrap();
-
-    let parts: Vec<&str> = text.split(' ').collect();
-    if parts.len() != 2 {
-        let msg = format!("got {} parts, expected 2", parts.len());
-        error!("{}", msg);
-        bot_say(SlackChannel::Library, &msg);
-        return;
+// every tag in the library with how many links have it, most used first
+pub fn tags_table(workspace: &Workspace) -> String {
+    let counts = match workspace.library.tag_counts() {
+        Ok(counts) => counts,
+        Err(e) => return format!("unable to read the library: {}", e),
+    };
+    if counts.is_empty() {
+        return String::from("no tags yet!");
     }
 
-    let input_string = parts[1];
-    let url_string = parse_slack_url(input_string);
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(row!["tag", "links"]);
+    for (tag, count) in counts {
+        table.add_row(row![format!("#{}", tag), count]);
+    }
+    table.to_string()
+}
 
-    if let Ok(parsed_url) = Url::parse(&url_string) {
-        info!("success! parsed {} as url: {}", url_string, parsed_url);
-        let add_request = AddRequest {
-            channel: Some(&channel),
-            timestamp: Some(timestamp),
-            name: if put_url(&parsed_url.as_str(), &user).is_ok() {
-                "heavy_check_mark"
-            } else {
-                "x"
-            },
-            ..Default::default()
-        };
-        add_reaction(add_request)
-    } else {
-        let msg = format!("unable to parse as url: {}", input_string);
-        error!("{}", msg);
+// add and remove a link's tags with +tag and -tag, answering with what to tell the user
+pub fn edit_tags(id: &str, changes: &[String], workspace: &Workspace) -> Result<String, String> {
+    let record = workspace
+        .library
+        .get(id)
+        .map_err(|e| format!("unable to read the library: {}", e))?;
+    let mut tags = match record {
+        Some(record) => record.tags,
+        None => return Err(format!("there's no record {}", id)),
+    };
+    for change in changes {
+        if let Some(tag) = change.strip_prefix('+') {
+            let tag = parse_tag(tag)?;
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        } else if let Some(tag) = change.strip_prefix('-') {
+            let tag = parse_tag(tag)?;
+            tags.retain(|t| *t != tag);
+        } else {
+            return Err(format!(
+                "{} should be +tag to add it or -tag to remove it",
+                change
+            ));
+        }
+    }
 
-        bot_say(SlackChannel::Library, &msg)
+    match workspace.library.set_tags(id, &tags) {
+        Ok(true) if tags.is_empty() => Ok(format!("{} has no tags", id)),
+        Ok(true) => Ok(format!("{} is tagged {}", id, tags_text(&tags))),
+        Ok(false) => Err(format!("there's no record {}", id)),
+        Err(e) => Err(format!("unable to tag {}: {}", id, e)),
     }
 }
 
-// get the five most recent entries from the DB
-pub fn last_five(message: MessageStandard) {
-    let client = DynamoDbClient::new(Region::UsEast1);
+// the five entries that best match the terms, as a table
+pub fn search_table(terms: &[&str], workspace: &Workspace) -> String {
+    // #tag finds links tagged tag
+    let terms: Vec<String> = terms
+        .iter()
+        .map(|t| t.trim_start_matches('#').to_string())
+        .filter(|t| !t.is_empty())
+        .collect();
+    match workspace.library.search(&terms, 5) {
+        Ok(records) => records_table(&records),
+        Err(e) => format!("unable to read the library: {}", e),
+    }
+}
 
-    let mut attr_values: HashMap<String, AttributeValue> = HashMap::new();
-    let mut attr_names: HashMap<String, String> = HashMap::new();
-    attr_names.insert(String::from("#timestamp"), String::from("timestamp"));
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let attr_value = AttributeValue {
-        s: Some(String::from("records")),
-        ..Default::default()
-    };
-    attr_values.insert(String::from(":partition"), attr_value);
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
 
-    // TODO: change to a dynamic time range, so maybe only look at records in the last 6mo? Really
-    // depends on the frequency of use which is currently unknown.
-    let attr_value = AttributeValue {
-        s: Some(String::from("1577836800")),
-        ..Default::default()
-    };
-    attr_values.insert(String::from(":t1"), attr_value);
-
-    // define the query
-    let query_input = QueryInput {
-        table_name: String::from("library"),
-        select: Some(String::from("ALL_ATTRIBUTES")),
-        index_name: Some(String::from("partition_key-timestamp-index")),
-        limit: Some(5),
-        // sort in reverse order, where newest are listed first
-        scan_index_forward: Some(false),
-        key_condition_expression: Some(String::from(
-            "partition_key = :partition AND #timestamp >= :t1",
-        )),
-        expression_attribute_names: Some(attr_names),
-        expression_attribute_values: Some(attr_values),
-        ..Default::default()
-    };
+    #[test]
+    fn tags_are_lowercase_without_the_hash() {
+        assert_eq!(parse_tag("#Rust"), Ok(String::from("rust")));
+        assert_eq!(parse_tag("rust"), Ok(String::from("rust")));
+        assert_eq!(parse_tag("#c++"), Ok(String::from("c++")));
+    }
 
-    let query_output = client.query(query_input).sync().unwrap();
-    debug!("{:?}", query_output);
-    let items = query_output.items.unwrap();
+    #[test]
+    fn channel_links_are_tags_by_name() {
+        assert_eq!(parse_tag("<#C0123ABC|rust>"), Ok(String::from("rust")));
+        assert!(parse_tag("<#C0123ABC>").is_err());
+    }
 
-    let channel: String = message.channel.unwrap();
+    #[test]
+    fn bad_tags_are_refused() {
+        assert!(parse_tag("#").is_err());
+        assert!(parse_tag("#foo/bar").is_err());
+        assert!(parse_tag(&format!("#{}", "a".repeat(MAX_TAG_CHARS))).is_ok());
+        assert!(parse_tag(&format!("#{}", "a".repeat(MAX_TAG_CHARS + 1))).is_err());
+    }
 
-    for chan in &[SlackChannel::BotSpam, SlackChannel::Library] {
-        if channel == chan.to_string() {
-            if items.is_empty() {
-                let msg = String::from("no records found!");
-                bot_say(chan.clone(), &msg);
-                return;
-            }
+    #[test]
+    fn put_tags_are_deduplicated() {
+        let tags = parse_put_tags(&words(&["#rust", "#Rust", "<#C0123ABC|rust>", "#async"]));
+        assert_eq!(tags, Ok(words(&["rust", "async"])));
+    }
 
-            let mut table = Table::new();
-            table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
-            table.set_titles(row!["user", "timestamp", "url"]);
-            for item in items.iter() {
-                let mut row: Vec<Cell> = Vec::new();
-                for key in vec!["real_name", "timestamp", "url"].iter() {
-                    let value = item.get(&(*key).to_string()).unwrap().s.as_ref().unwrap();
-                    if key == &"timestamp" {
-                        let timestamp_int = value.parse::<i64>().unwrap();
-                        let dt = format!("{}", Utc.timestamp(timestamp_int, 0));
-                        row.push(Cell::new(&dt));
-                    } else {
-                        row.push(Cell::new(&value));
-                    }
-                }
-                table.add_row(Row::new(row));
-            }
-            let msg = table.to_string();
-            bot_say(chan.clone(), &msg)
-        }
+    #[test]
+    fn put_tags_need_a_hash() {
+        assert!(parse_put_tags(&words(&["#rust", "async"])).is_err());
+        assert_eq!(parse_put_tags(&[]), Ok(Vec::new()));
     }
 }
@@ -0,0 +1,951 @@
+//! where the library's links are kept
+//!
+//! each workspace has one store, picked with library_store in workspaces.toml (or
+//! RUSTY_LIBRARY_STORE without one):
+//!
+//! - dynamodb (the default): the table in library_table, in us-east-1
+//! - sqlite: a database file at library_path (or RUSTY_LIBRARY_PATH), created if it isn't there
+//! - memory: nothing is kept once the bot stops, for running locally
+use crate::metadata::PageMetadata;
+use failure::{bail, format_err, Error};
+use log::{debug, info};
+use rusoto_core::Region;
+use rusoto_dynamodb::{
+    AttributeValue, DeleteItemInput, DescribeTableInput, DynamoDb, DynamoDbClient, PutItemInput,
+    QueryInput, UpdateItemInput,
+};
+use rusqlite::{params, Connection, Row, NO_PARAMS};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+const LIBRARY_STORE_ENV_VAR: &str = "RUSTY_LIBRARY_STORE";
+const LIBRARY_PATH_ENV_VAR: &str = "RUSTY_LIBRARY_PATH";
+const DEFAULT_LIBRARY_PATH: &str = "library.db";
+
+// a link someone saved
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub id: String,
+    pub url: String,
+    // seconds since the epoch
+    pub timestamp: i64,
+    // the user's id, and their name and handle when the link was saved
+    #[allow(dead_code)]
+    pub user: String,
+    pub real_name: String,
+    pub handle: String,
+    // what the page said about itself when it was saved
+    pub metadata: PageMetadata,
+    // lowercase, without the #
+    pub tags: Vec<String>,
+}
+
+// a link to save, the store gives it its id
+#[derive(Debug, Clone)]
+pub struct NewRecord {
+    pub url: String,
+    pub timestamp: i64,
+    pub user: String,
+    pub real_name: String,
+    pub handle: String,
+    // what the page said about itself when it was saved
+    pub metadata: PageMetadata,
+    // lowercase, without the #
+    pub tags: Vec<String>,
+}
+
+impl NewRecord {
+    fn with_id(self, id: String) -> Record {
+        Record {
+            id,
+            url: self.url,
+            timestamp: self.timestamp,
+            user: self.user,
+            real_name: self.real_name,
+            handle: self.handle,
+            metadata: self.metadata,
+            tags: self.tags,
+        }
+    }
+}
+
+pub trait LibraryStore: Send + Sync {
+    fn put(&self, record: NewRecord) -> Result<Record, Error>;
+
+    // newest first, at most `limit` of them if there's a limit
+    fn query(&self, limit: Option<usize>) -> Result<Vec<Record>, Error>;
+
+    // the newest `limit` records with the tag
+    fn query_tag(&self, tag: &str, limit: usize) -> Result<Vec<Record>, Error>;
+
+    fn get(&self, id: &str) -> Result<Option<Record>, Error>;
+
+    // every tag with how many records have it, most used first
+    fn tag_counts(&self) -> Result<Vec<(String, usize)>, Error>;
+
+    // returns whether there was a record with the id. no command deletes links yet.
+    #[allow(dead_code)]
+    fn delete(&self, id: &str) -> Result<bool, Error>;
+
+    // replace a record's tags, returning whether there was a record with the id
+    fn set_tags(&self, id: &str, tags: &[String]) -> Result<bool, Error>;
+
+    // the records that match any of the terms, best match first and newest first among equals
+    fn search(&self, terms: &[String], limit: usize) -> Result<Vec<Record>, Error> {
+        let terms: Vec<String> = terms.iter().map(|t| t.to_lowercase()).collect();
+        let mut found: Vec<(usize, Record)> = self
+            .query(None)?
+            .into_iter()
+            .map(|record| (relevance(&record, &terms), record))
+            .filter(|(score, _)| *score > 0)
+            .collect();
+        // query gives newest first and the sort is stable, so that's the tiebreak
+        found.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        Ok(found
+            .into_iter()
+            .take(limit)
+            .map(|(_, record)| record)
+            .collect())
+    }
+}
+
+impl Record {
+    // the text a search looks through, lowercased, with how much a match in each counts
+    fn search_fields(&self) -> Vec<(String, usize)> {
+        let mut fields = vec![(self.url.to_lowercase(), 1)];
+        if let Some(title) = &self.metadata.title {
+            fields.push((title.to_lowercase(), 3));
+        }
+        if let Some(description) = &self.metadata.description {
+            fields.push((description.to_lowercase(), 2));
+        }
+        if !self.tags.is_empty() {
+            fields.push((self.tags.join(" "), 3));
+        }
+        fields
+    }
+}
+
+// how much a term found in `text` is worth: twice as much as a whole word as inside one
+fn term_score(text: &str, term: &str) -> usize {
+    if text
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| word == term)
+    {
+        2
+    } else if text.contains(term) {
+        1
+    } else {
+        0
+    }
+}
+
+// how well a record matches the (lowercase) terms, 0 if it doesn't match any. each term counts
+// for the best field it's found in.
+fn relevance(record: &Record, terms: &[String]) -> usize {
+    let fields = record.search_fields();
+    terms
+        .iter()
+        .map(|term| {
+            fields
+                .iter()
+                .map(|(text, weight)| term_score(text, term) * weight)
+                .max()
+                .unwrap_or(0)
+        })
+        .sum()
+}
+
+// count the tags in each record's list of them, most used first and alphabetically among equals
+fn count_tags(tag_lists: impl Iterator<Item = Vec<String>>) -> Vec<(String, usize)> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for tag in tag_lists.flatten() {
+        *counts.entry(tag).or_insert(0) += 1;
+    }
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+    counts
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreKind {
+    DynamoDb,
+    Sqlite,
+    Memory,
+}
+
+impl std::str::FromStr for StoreKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<StoreKind, Error> {
+        match s.to_lowercase().as_str() {
+            "dynamodb" | "dynamo" => Ok(StoreKind::DynamoDb),
+            "sqlite" => Ok(StoreKind::Sqlite),
+            "memory" => Ok(StoreKind::Memory),
+            _ => bail!(
+                "unknown library store '{}', expected one of: dynamodb, sqlite, memory",
+                s
+            ),
+        }
+    }
+}
+
+pub fn library_store_kind() -> String {
+    std::env::var(LIBRARY_STORE_ENV_VAR).unwrap_or_else(|_| String::from("dynamodb"))
+}
+
+pub fn library_path() -> String {
+    std::env::var(LIBRARY_PATH_ENV_VAR).unwrap_or_else(|_| String::from(DEFAULT_LIBRARY_PATH))
+}
+
+// open the store of the given kind. `table` is only used by dynamodb and `path` by sqlite.
+pub fn open_store(
+    kind: StoreKind,
+    table: &str,
+    path: &str,
+) -> Result<Box<dyn LibraryStore>, Error> {
+    info!("using a {:?} library", kind);
+    Ok(match kind {
+        StoreKind::DynamoDb => Box::new(DynamoStore::new(table)?),
+        StoreKind::Sqlite => Box::new(SqliteStore::open(path)?),
+        StoreKind::Memory => Box::new(MemoryStore::default()),
+    })
+}
+
+pub struct DynamoStore {
+    client: DynamoDbClient,
+    table: String,
+    // the names of the table's key attributes, which delete_item and update_item need
+    key_names: Vec<String>,
+}
+
+fn string_value(s: &str) -> AttributeValue {
+    AttributeValue {
+        s: Some(s.to_string()),
+        ..Default::default()
+    }
+}
+
+fn tags_value(tags: &[String]) -> AttributeValue {
+    AttributeValue {
+        ss: Some(tags.to_vec()),
+        ..Default::default()
+    }
+}
+
+fn string_attr(item: &HashMap<String, AttributeValue>, name: &str) -> String {
+    optional_attr(item, name).unwrap_or_default()
+}
+
+fn optional_attr(item: &HashMap<String, AttributeValue>, name: &str) -> Option<String> {
+    item.get(name).and_then(|v| v.s.clone())
+}
+
+// ids start with the time the record was saved, so a record can be found with the timestamp
+// index. records saved before they had ids go by their timestamp alone.
+fn new_id(timestamp: i64) -> String {
+    let random = Uuid::new_v4().to_simple().to_string();
+    format!("{}-{}", timestamp, &random[..8])
+}
+
+fn timestamp_of(id: &str) -> &str {
+    id.split('-').next().unwrap_or_default()
+}
+
+fn record_from_item(item: &HashMap<String, AttributeValue>) -> Record {
+    let timestamp = string_attr(item, "timestamp");
+    let id = optional_attr(item, "id").unwrap_or_else(|| timestamp.clone());
+    Record {
+        id,
+        url: string_attr(item, "url"),
+        timestamp: timestamp.parse().unwrap_or_default(),
+        user: string_attr(item, "user"),
+        real_name: string_attr(item, "real_name"),
+        handle: string_attr(item, "handle"),
+        metadata: PageMetadata {
+            title: optional_attr(item, "title"),
+            description: optional_attr(item, "description"),
+            image: optional_attr(item, "image"),
+            site_name: optional_attr(item, "site_name"),
+            canonical_url: optional_attr(item, "canonical_url"),
+        },
+        tags: item
+            .get("tags")
+            .and_then(|v| v.ss.clone())
+            .unwrap_or_default(),
+    }
+}
+
+impl DynamoStore {
+    pub fn new(table: &str) -> Result<DynamoStore, Error> {
+        let client = DynamoDbClient::new(Region::UsEast1);
+        let input = DescribeTableInput {
+            table_name: String::from(table),
+        };
+        let description = client
+            .describe_table(input)
+            .sync()
+            .map_err(|e| format_err!("unable to describe table {}: {}", table, e))?
+            .table
+            .ok_or_else(|| format_err!("no description for table {}", table))?;
+        let key_names = description
+            .key_schema
+            .unwrap_or_default()
+            .into_iter()
+            .map(|key| key.attribute_name)
+            .collect();
+        Ok(DynamoStore {
+            client,
+            table: String::from(table),
+            key_names,
+        })
+    }
+
+    // a query of the timestamp index for the records whose timestamp is `op` `timestamp`,
+    // newest first
+    fn index_query(&self, op: &str, timestamp: &str) -> QueryInput {
+        let mut attr_values: HashMap<String, AttributeValue> = HashMap::new();
+        let mut attr_names: HashMap<String, String> = HashMap::new();
+        attr_names.insert(String::from("#timestamp"), String::from("timestamp"));
+        attr_values.insert(String::from(":partition"), string_value("records"));
+        attr_values.insert(String::from(":t1"), string_value(timestamp));
+        QueryInput {
+            table_name: self.table.clone(),
+            select: Some(String::from("ALL_ATTRIBUTES")),
+            index_name: Some(String::from("partition_key-timestamp-index")),
+            // sort in reverse order, where newest are listed first
+            scan_index_forward: Some(false),
+            key_condition_expression: Some(format!(
+                "partition_key = :partition AND #timestamp {} :t1",
+                op
+            )),
+            expression_attribute_names: Some(attr_names),
+            expression_attribute_values: Some(attr_values),
+            ..Default::default()
+        }
+    }
+
+    // every record since the library started, newest first
+    fn all_records_query(&self) -> QueryInput {
+        // TODO: change to a dynamic time range, so maybe only look at records in the last 6mo?
+        // Really depends on the frequency of use which is currently unknown.
+        self.index_query(">=", "1577836800")
+    }
+
+    // run a query until it has found `limit` items, or all of them if there's no limit
+    fn run_query(
+        &self,
+        mut input: QueryInput,
+        limit: Option<usize>,
+    ) -> Result<Vec<HashMap<String, AttributeValue>>, Error> {
+        // dynamodb's limit is on the items read, before any filter, so it's only the same as ours
+        // without one
+        if input.filter_expression.is_none() {
+            input.limit = limit.map(|l| l as i64);
+        }
+        let mut items = Vec::new();
+        loop {
+            let query_output = self.client.query(input.clone()).sync()?;
+            debug!("{:?}", query_output);
+            items.extend(query_output.items.unwrap_or_default());
+
+            // a query returns a page at a time, keep going until we have enough
+            input.exclusive_start_key = query_output.last_evaluated_key;
+            let enough = limit.is_some_and(|limit| items.len() >= limit);
+            if enough || input.exclusive_start_key.is_none() {
+                items.truncate(limit.unwrap_or(items.len()));
+                return Ok(items);
+            }
+        }
+    }
+
+    // the record with the id, found with the timestamp its id starts with
+    fn find_item(&self, id: &str) -> Result<Option<HashMap<String, AttributeValue>>, Error> {
+        let timestamp = timestamp_of(id);
+        if timestamp.is_empty() || !timestamp.chars().all(|c| c.is_ascii_digit()) {
+            return Ok(None);
+        }
+        Ok(self
+            .run_query(self.index_query("=", timestamp), None)?
+            .into_iter()
+            .find(|item| record_from_item(item).id == id))
+    }
+
+    // the key of the record with the id, if there's a record with the id
+    fn find_key(&self, id: &str) -> Result<Option<HashMap<String, AttributeValue>>, Error> {
+        let item = match self.find_item(id)? {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        let mut key = HashMap::new();
+        for name in &self.key_names {
+            let value = item
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format_err!("record {} has no {}", id, name))?;
+            key.insert(name.clone(), value);
+        }
+        Ok(Some(key))
+    }
+}
+
+impl LibraryStore for DynamoStore {
+    fn put(&self, record: NewRecord) -> Result<Record, Error> {
+        let id = new_id(record.timestamp);
+        let mut item: HashMap<String, AttributeValue> = HashMap::new();
+        item.insert(String::from("partition_key"), string_value("records"));
+        item.insert(String::from("id"), string_value(&id));
+        item.insert(String::from("url"), string_value(&record.url));
+        item.insert(
+            String::from("timestamp"),
+            string_value(&record.timestamp.to_string()),
+        );
+        item.insert(String::from("user"), string_value(&record.user));
+        item.insert(String::from("real_name"), string_value(&record.real_name));
+        item.insert(String::from("handle"), string_value(&record.handle));
+        let metadata = &record.metadata;
+        for (name, value) in [
+            ("title", &metadata.title),
+            ("description", &metadata.description),
+            ("image", &metadata.image),
+            ("site_name", &metadata.site_name),
+            ("canonical_url", &metadata.canonical_url),
+        ] {
+            if let Some(value) = value {
+                item.insert(String::from(name), string_value(value));
+            }
+        }
+        // dynamodb doesn't allow empty sets, so no tags is no attribute
+        if !record.tags.is_empty() {
+            item.insert(String::from("tags"), tags_value(&record.tags));
+        }
+
+        // never write over another record, however unlikely it is to have the same key. key
+        // names like url and timestamp are reserved words, so they go in as #k0, #k1...
+        let mut names = HashMap::new();
+        let mut condition = Vec::new();
+        for (idx, name) in self.key_names.iter().enumerate() {
+            names.insert(format!("#k{}", idx), name.clone());
+            condition.push(format!("attribute_not_exists(#k{})", idx));
+        }
+        let put_item_input = PutItemInput {
+            table_name: self.table.clone(),
+            item,
+            condition_expression: Some(condition.join(" AND ")),
+            expression_attribute_names: Some(names),
+            ..Default::default()
+        };
+        let res = self.client.put_item(put_item_input).sync()?;
+        debug!("{:?}", res);
+        Ok(record.with_id(id))
+    }
+
+    fn query(&self, limit: Option<usize>) -> Result<Vec<Record>, Error> {
+        Ok(self
+            .run_query(self.all_records_query(), limit)?
+            .iter()
+            .map(record_from_item)
+            .collect())
+    }
+
+    fn query_tag(&self, tag: &str, limit: usize) -> Result<Vec<Record>, Error> {
+        let mut input = self.all_records_query();
+        input.filter_expression = Some(String::from("contains(#tags, :tag)"));
+        if let Some(names) = input.expression_attribute_names.as_mut() {
+            names.insert(String::from("#tags"), String::from("tags"));
+        }
+        if let Some(values) = input.expression_attribute_values.as_mut() {
+            values.insert(String::from(":tag"), string_value(tag));
+        }
+        Ok(self
+            .run_query(input, Some(limit))?
+            .iter()
+            .map(record_from_item)
+            .collect())
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Record>, Error> {
+        Ok(self.find_item(id)?.as_ref().map(record_from_item))
+    }
+
+    fn tag_counts(&self) -> Result<Vec<(String, usize)>, Error> {
+        // only the tags are read, and only for records that have some
+        let mut input = self.all_records_query();
+        input.select = Some(String::from("SPECIFIC_ATTRIBUTES"));
+        input.projection_expression = Some(String::from("#tags"));
+        input.filter_expression = Some(String::from("attribute_exists(#tags)"));
+        if let Some(names) = input.expression_attribute_names.as_mut() {
+            names.insert(String::from("#tags"), String::from("tags"));
+        }
+        let items = self.run_query(input, None)?;
+        Ok(count_tags(items.into_iter().map(|item| {
+            item.get("tags")
+                .and_then(|v| v.ss.clone())
+                .unwrap_or_default()
+        })))
+    }
+
+    fn delete(&self, id: &str) -> Result<bool, Error> {
+        let key = match self.find_key(id)? {
+            Some(key) => key,
+            None => return Ok(false),
+        };
+        let input = DeleteItemInput {
+            table_name: self.table.clone(),
+            key,
+            ..Default::default()
+        };
+        self.client.delete_item(input).sync()?;
+        Ok(true)
+    }
+
+    fn set_tags(&self, id: &str, tags: &[String]) -> Result<bool, Error> {
+        let key = match self.find_key(id)? {
+            Some(key) => key,
+            None => return Ok(false),
+        };
+        let mut input = UpdateItemInput {
+            table_name: self.table.clone(),
+            key,
+            update_expression: Some(String::from("REMOVE tags")),
+            ..Default::default()
+        };
+        if !tags.is_empty() {
+            let mut values = HashMap::new();
+            values.insert(String::from(":tags"), tags_value(tags));
+            input.update_expression = Some(String::from("SET tags = :tags"));
+            input.expression_attribute_values = Some(values);
+        }
+        self.client.update_item(input).sync()?;
+        Ok(true)
+    }
+}
+
+// a connection can't be shared between threads, so it's used by one at a time
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+// columns added since the table was first created, which older databases won't have yet
+const ADDED_COLUMNS: &[(&str, &str)] = &[
+    ("title", "TEXT"),
+    ("description", "TEXT"),
+    ("image", "TEXT"),
+    ("site_name", "TEXT"),
+    ("canonical_url", "TEXT"),
+    // space separated, tags never have spaces in them
+    ("tags", "TEXT NOT NULL DEFAULT ''"),
+];
+
+const SELECT_RECORDS: &str = "SELECT id, url, timestamp, user, real_name, handle, title, \
+     description, image, site_name, canonical_url, tags FROM records";
+
+fn record_from_row(row: &Row) -> rusqlite::Result<Record> {
+    Ok(Record {
+        id: row.get::<_, i64>(0)?.to_string(),
+        url: row.get(1)?,
+        timestamp: row.get(2)?,
+        user: row.get(3)?,
+        real_name: row.get(4)?,
+        handle: row.get(5)?,
+        metadata: PageMetadata {
+            title: row.get(6)?,
+            description: row.get(7)?,
+            image: row.get(8)?,
+            site_name: row.get(9)?,
+            canonical_url: row.get(10)?,
+        },
+        tags: row
+            .get::<_, String>(11)?
+            .split_whitespace()
+            .map(String::from)
+            .collect(),
+    })
+}
+
+impl SqliteStore {
+    pub fn open(path: &str) -> Result<SqliteStore, Error> {
+        let conn = Connection::open(path)
+            .map_err(|e| format_err!("unable to open library {}: {}", path, e))?;
+        SqliteStore::with_connection(conn, path)
+    }
+
+    // create the table if it isn't there, and bring it up to date if it is
+    fn with_connection(conn: Connection, path: &str) -> Result<SqliteStore, Error> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS records (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                url TEXT NOT NULL,
+                timestamp INTEGER NOT NULL,
+                user TEXT NOT NULL,
+                real_name TEXT NOT NULL,
+                handle TEXT NOT NULL
+            )",
+            NO_PARAMS,
+        )?;
+
+        let columns: Vec<String> = conn
+            .prepare("PRAGMA table_info(records)")?
+            .query_map(NO_PARAMS, |row| row.get(1))?
+            .collect::<Result<_, _>>()?;
+        for (name, kind) in ADDED_COLUMNS {
+            if !columns.iter().any(|column| column == name) {
+                info!("adding {} to library {}", name, path);
+                let sql = format!("ALTER TABLE records ADD COLUMN {} {}", name, kind);
+                conn.execute(&sql, NO_PARAMS)?;
+            }
+        }
+        Ok(SqliteStore {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+impl LibraryStore for SqliteStore {
+    fn put(&self, record: NewRecord) -> Result<Record, Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO records (url, timestamp, user, real_name, handle, title, description,
+                image, site_name, canonical_url, tags)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                record.url,
+                record.timestamp,
+                record.user,
+                record.real_name,
+                record.handle,
+                record.metadata.title,
+                record.metadata.description,
+                record.metadata.image,
+                record.metadata.site_name,
+                record.metadata.canonical_url,
+                record.tags.join(" ")
+            ],
+        )?;
+        let id = conn.last_insert_rowid().to_string();
+        Ok(record.with_id(id))
+    }
+
+    fn query(&self, limit: Option<usize>) -> Result<Vec<Record>, Error> {
+        let conn = self.conn.lock().unwrap();
+        // a negative limit is no limit at all
+        let limit = limit.map(|l| l as i64).unwrap_or(-1);
+        let sql = format!(
+            "{} ORDER BY timestamp DESC, id DESC LIMIT ?1",
+            SELECT_RECORDS
+        );
+        let mut statement = conn.prepare(&sql)?;
+        let records = statement
+            .query_map(params![limit], record_from_row)?
+            .collect::<Result<Vec<Record>, _>>()?;
+        Ok(records)
+    }
+
+    fn query_tag(&self, tag: &str, limit: usize) -> Result<Vec<Record>, Error> {
+        let conn = self.conn.lock().unwrap();
+        // padded with spaces so only whole tags match
+        let sql = format!(
+            "{} WHERE instr(' ' || tags || ' ', ' ' || ?1 || ' ') > 0
+             ORDER BY timestamp DESC, id DESC LIMIT ?2",
+            SELECT_RECORDS
+        );
+        let mut statement = conn.prepare(&sql)?;
+        let records = statement
+            .query_map(params![tag, limit as i64], record_from_row)?
+            .collect::<Result<Vec<Record>, _>>()?;
+        Ok(records)
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Record>, Error> {
+        let id: i64 = match id.parse() {
+            Ok(id) => id,
+            Err(_) => return Ok(None),
+        };
+        let conn = self.conn.lock().unwrap();
+        let sql = format!("{} WHERE id = ?1", SELECT_RECORDS);
+        let mut statement = conn.prepare(&sql)?;
+        let mut records = statement.query_map(params![id], record_from_row)?;
+        Ok(records.next().transpose()?)
+    }
+
+    fn tag_counts(&self) -> Result<Vec<(String, usize)>, Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare("SELECT tags FROM records WHERE tags != ''")?;
+        let tag_lists = statement
+            .query_map(NO_PARAMS, |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(count_tags(tag_lists.iter().map(|tags| {
+            tags.split_whitespace().map(String::from).collect()
+        })))
+    }
+
+    fn delete(&self, id: &str) -> Result<bool, Error> {
+        let id: i64 = match id.parse() {
+            Ok(id) => id,
+            Err(_) => return Ok(false),
+        };
+        let conn = self.conn.lock().unwrap();
+        let deleted = conn.execute("DELETE FROM records WHERE id = ?1", params![id])?;
+        Ok(deleted > 0)
+    }
+
+    fn set_tags(&self, id: &str, tags: &[String]) -> Result<bool, Error> {
+        let id: i64 = match id.parse() {
+            Ok(id) => id,
+            Err(_) => return Ok(false),
+        };
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE records SET tags = ?1 WHERE id = ?2",
+            params![tags.join(" "), id],
+        )?;
+        Ok(updated > 0)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    // the id the next record gets, and the records oldest first
+    records: Mutex<(u64, Vec<Record>)>,
+}
+
+impl LibraryStore for MemoryStore {
+    fn put(&self, record: NewRecord) -> Result<Record, Error> {
+        let mut records = self.records.lock().unwrap();
+        records.0 += 1;
+        let record = record.with_id(records.0.to_string());
+        records.1.push(record.clone());
+        Ok(record)
+    }
+
+    fn query(&self, limit: Option<usize>) -> Result<Vec<Record>, Error> {
+        let records = self.records.lock().unwrap();
+        // reversed first so that records saved in the same second are newest first too
+        let mut newest: Vec<Record> = records.1.iter().rev().cloned().collect();
+        newest.sort_by_key(|record| std::cmp::Reverse(record.timestamp));
+        newest.truncate(limit.unwrap_or(newest.len()));
+        Ok(newest)
+    }
+
+    fn query_tag(&self, tag: &str, limit: usize) -> Result<Vec<Record>, Error> {
+        Ok(self
+            .query(None)?
+            .into_iter()
+            .filter(|record| record.tags.iter().any(|t| t == tag))
+            .take(limit)
+            .collect())
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Record>, Error> {
+        let records = self.records.lock().unwrap();
+        Ok(records.1.iter().find(|record| record.id == id).cloned())
+    }
+
+    fn tag_counts(&self) -> Result<Vec<(String, usize)>, Error> {
+        let records = self.records.lock().unwrap();
+        Ok(count_tags(
+            records.1.iter().map(|record| record.tags.clone()),
+        ))
+    }
+
+    fn delete(&self, id: &str) -> Result<bool, Error> {
+        let mut records = self.records.lock().unwrap();
+        let before = records.1.len();
+        records.1.retain(|record| record.id != id);
+        Ok(records.1.len() < before)
+    }
+
+    fn set_tags(&self, id: &str, tags: &[String]) -> Result<bool, Error> {
+        let mut records = self.records.lock().unwrap();
+        match records.1.iter_mut().find(|record| record.id == id) {
+            Some(record) => {
+                record.tags = tags.to_vec();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_record(url: &str, timestamp: i64, tags: &[&str]) -> NewRecord {
+        NewRecord {
+            url: String::from(url),
+            timestamp,
+            user: String::from("U123"),
+            real_name: String::from("someone"),
+            handle: String::from("someone"),
+            metadata: PageMetadata::default(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    fn urls(records: &[Record]) -> Vec<&str> {
+        records.iter().map(|r| r.url.as_str()).collect()
+    }
+
+    fn sqlite() -> SqliteStore {
+        SqliteStore::open(":memory:").unwrap()
+    }
+
+    // everything a store should do, the same whichever store it is
+    fn check_store(store: &dyn LibraryStore) {
+        let first = store
+            .put(new_record("https://a.com", 10, &["rust"]))
+            .unwrap();
+        store.put(new_record("https://b.com", 30, &[])).unwrap();
+        let third = store
+            .put(new_record("https://c.com", 20, &["rust", "async"]))
+            .unwrap();
+        assert_ne!(first.id, third.id);
+
+        let all = store.query(None).unwrap();
+        assert_eq!(
+            urls(&all),
+            ["https://b.com", "https://c.com", "https://a.com"]
+        );
+        assert_eq!(
+            urls(&store.query(Some(2)).unwrap()),
+            ["https://b.com", "https://c.com"]
+        );
+
+        let tagged = store.query_tag("rust", 5).unwrap();
+        assert_eq!(urls(&tagged), ["https://c.com", "https://a.com"]);
+        assert_eq!(
+            urls(&store.query_tag("rust", 1).unwrap()),
+            ["https://c.com"]
+        );
+        assert!(store.query_tag("rus", 5).unwrap().is_empty());
+        assert_eq!(
+            store.tag_counts().unwrap(),
+            [(String::from("rust"), 2), (String::from("async"), 1)]
+        );
+
+        let tags = vec![String::from("go")];
+        assert!(store.set_tags(&first.id, &tags).unwrap());
+        assert_eq!(store.get(&first.id).unwrap().unwrap().tags, tags);
+        assert!(store.set_tags(&first.id, &[]).unwrap());
+        assert!(store.get(&first.id).unwrap().unwrap().tags.is_empty());
+        assert!(!store.set_tags("999", &tags).unwrap());
+
+        assert!(store.delete(&first.id).unwrap());
+        assert!(store.get(&first.id).unwrap().is_none());
+        assert!(!store.delete(&first.id).unwrap());
+        assert!(!store.delete("999").unwrap());
+        assert!(!store.delete("not an id").unwrap());
+        assert_eq!(store.query(None).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn memory_store() {
+        check_store(&MemoryStore::default());
+    }
+
+    #[test]
+    fn sqlite_store() {
+        check_store(&sqlite());
+    }
+
+    #[test]
+    fn sqlite_records_saved_in_the_same_second_are_newest_first() {
+        let store = sqlite();
+        store.put(new_record("https://a.com", 10, &[])).unwrap();
+        store.put(new_record("https://b.com", 10, &[])).unwrap();
+        assert_eq!(
+            urls(&store.query(None).unwrap()),
+            ["https://b.com", "https://a.com"]
+        );
+    }
+
+    #[test]
+    fn sqlite_keeps_metadata() {
+        let store = sqlite();
+        let mut record = new_record("https://a.com", 10, &[]);
+        record.metadata.title = Some(String::from("a title"));
+        record.metadata.canonical_url = Some(String::from("https://a.com/"));
+        let id = store.put(record).unwrap().id;
+
+        let metadata = store.get(&id).unwrap().unwrap().metadata;
+        assert_eq!(metadata.title.as_deref(), Some("a title"));
+        assert_eq!(metadata.canonical_url.as_deref(), Some("https://a.com/"));
+        assert_eq!(metadata.description, None);
+    }
+
+    #[test]
+    fn sqlite_adds_new_columns_to_an_old_table() {
+        // the table as it was before there were any added columns
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE records (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                url TEXT NOT NULL,
+                timestamp INTEGER NOT NULL,
+                user TEXT NOT NULL,
+                real_name TEXT NOT NULL,
+                handle TEXT NOT NULL
+            )",
+            NO_PARAMS,
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO records (url, timestamp, user, real_name, handle)
+             VALUES ('https://old.com', 1, 'U123', 'someone', 'someone')",
+            NO_PARAMS,
+        )
+        .unwrap();
+
+        let store = SqliteStore::with_connection(conn, ":memory:").unwrap();
+        let old = store.query(None).unwrap();
+        assert_eq!(urls(&old), ["https://old.com"]);
+        assert!(old[0].tags.is_empty());
+        assert_eq!(old[0].metadata.title, None);
+
+        assert!(store.set_tags(&old[0].id, &[String::from("rust")]).unwrap());
+        assert_eq!(
+            urls(&store.query_tag("rust", 5).unwrap()),
+            ["https://old.com"]
+        );
+    }
+
+    #[test]
+    fn dynamodb_ids_start_with_their_timestamp() {
+        let id = new_id(1600000000);
+        assert_eq!(timestamp_of(&id), "1600000000");
+        assert_ne!(id, new_id(1600000000));
+        // records from before there were ids go by their timestamp alone
+        assert_eq!(timestamp_of("1600000000"), "1600000000");
+    }
+
+    #[test]
+    fn search_ranks_by_relevance_then_recency() {
+        let store = MemoryStore::default();
+        store
+            .put(new_record("https://a.com/rustacean", 40, &[]))
+            .unwrap();
+        store
+            .put(new_record("https://b.com/rust", 10, &[]))
+            .unwrap();
+        store
+            .put(new_record("https://c.com/rust", 20, &[]))
+            .unwrap();
+        store.put(new_record("https://d.com/go", 50, &[])).unwrap();
+        store
+            .put(new_record("https://e.com/x", 5, &["rust"]))
+            .unwrap();
+
+        let found = store.search(&[String::from("Rust")], 5).unwrap();
+        assert_eq!(
+            urls(&found),
+            [
+                "https://e.com/x",
+                "https://c.com/rust",
+                "https://b.com/rust",
+                "https://a.com/rustacean"
+            ]
+        );
+    }
+}
@@ -1,38 +1,94 @@
+mod blocks;
+mod channels;
+mod commands;
+mod config;
+mod digest;
+mod discovery;
+mod events_api;
+mod fetch;
+mod filter;
+mod jsonfeed;
 mod library;
+mod library_store;
+mod metadata;
 mod reader;
+mod slash;
+mod socket_mode;
+mod state;
+mod subscriptions;
+mod supervisor;
+mod transport;
 mod utils;
+mod workspace;
 
 #[macro_use]
 extern crate prettytable;
-use library::{last_five, parse_put};
-use log::info;
-use reader::read_feeds;
+use config::Subscriptions;
+use failure::{format_err, Error};
+use log::{error, info};
+use reader::{read_feeds, FeedCommand};
+use serde_json::Value;
 use slack::{Event, EventHandler, Message, RtmClient};
+use slack_api::MessageStandard;
 use std::fmt;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use supervisor::supervise;
+use transport::Transport;
+use utils::say_to;
+use workspace::Workspace;
 
-struct Handler;
+// the workspaces with a feed loop running. each handler only starts one, and this makes sure
+// nothing else can start a second one that would post every article twice.
+static FEED_WORKERS: Mutex<Vec<String>> = Mutex::new(Vec::new());
 
-#[derive(Clone, Debug)]
-pub enum SlackChannel {
-    Aws,
-    Rust,
-    Kubernetes,
-    Python,
-    BotSpam,
-    Library,
+struct Handler {
+    workspace: Arc<Workspace>,
+    feed_commands: Sender<FeedCommand>,
+    // everything the feed loop needs to start. taken on the first connect.
+    feed_worker: Option<(Subscriptions, Receiver<FeedCommand>)>,
 }
 
+// a channel, by name. see channels::ChannelDirectory for turning it into an id.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SlackChannel(String);
+
 impl fmt::Display for SlackChannel {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let s = match self {
-            SlackChannel::Aws => "CA6MUA4LU",
-            SlackChannel::Rust => "C8EHWNKHV",
-            SlackChannel::Kubernetes => "C91DM9Y6S",
-            SlackChannel::Python => "C6DTBQK4P",
-            SlackChannel::BotSpam => "CNF841CN7",
-            SlackChannel::Library => "CE2L5QUGP",
-        };
-        write!(f, "{}", s)
+        write!(f, "{}", self.0)
+    }
+}
+
+impl SlackChannel {
+    // where the bot talks about itself, like feeds that keep failing
+    pub fn bot_spam() -> SlackChannel {
+        SlackChannel(String::from("botspam"))
+    }
+
+    pub fn library() -> SlackChannel {
+        SlackChannel(String::from("library"))
+    }
+
+    // the name used to refer to a channel in config files and commands
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::str::FromStr for SlackChannel {
+    type Err = String;
+
+    // takes a name with or without the leading #. slack names are lowercase and can't contain
+    // spaces or periods.
+    fn from_str(s: &str) -> Result<SlackChannel, String> {
+        let name = s.trim_start_matches('#').to_lowercase();
+        if name.is_empty()
+            || name.len() > 80
+            || name.contains(|c: char| c.is_whitespace() || c == '.')
+        {
+            return Err(format!("invalid channel name '{}'", s));
+        }
+        Ok(SlackChannel(name))
     }
 }
 
@@ -41,80 +97,193 @@ impl EventHandler for Handler {
     fn on_event(&mut self, client: &RtmClient, event: Event) {
         info!("on_event(event: {:?})", event);
 
-        if let Event::Message(message) = event.clone() {
-            self.handle_message(*message, client, &event)
+        if let Event::Message(message) = event {
+            if let Message::Standard(message_standard) = *message {
+                self.handle_message(message_standard)
+            }
         }
     }
 
     fn on_close(&mut self, client: &RtmClient) {}
 
     fn on_connect(&mut self, client: &RtmClient) {
-        let token = utils::get_slack_token_from_env_var();
-        std::thread::spawn(|| read_feeds(token));
+        self.start_feeds();
     }
 }
 
-#[allow(unused_variables)]
 impl Handler {
-    fn handle_message(&mut self, message: Message, client: &RtmClient, event: &Event) {
-        let message_standard = match message {
-            Message::Standard(message_standard) => message_standard,
-            _ => return,
+    fn new(workspace: Arc<Workspace>, subscriptions: Subscriptions) -> Handler {
+        let (feed_commands, commands) = channel();
+        Handler {
+            workspace,
+            feed_commands,
+            feed_worker: Some((subscriptions, commands)),
+        }
+    }
+
+    // a handler that shares this one's workspace and feed loop, for taking slash commands
+    // alongside a websocket connection
+    fn commands_only(&self) -> Handler {
+        Handler {
+            workspace: Arc::clone(&self.workspace),
+            feed_commands: self.feed_commands.clone(),
+            feed_worker: None,
+        }
+    }
+
+    // start the feed loop, unless it's already running. this is called on every connect, and
+    // the loop keeps running across reconnects.
+    fn start_feeds(&mut self) {
+        let (subscriptions, commands) = match self.feed_worker.take() {
+            Some(feed_worker) => feed_worker,
+            None => return,
         };
 
+        let mut running = FEED_WORKERS.lock().unwrap();
+        if running.contains(&self.workspace.name) {
+            error!("a feed loop is already running for {}", self.workspace.name);
+            return;
+        }
+        running.push(self.workspace.name.clone());
+
+        let workspace = Arc::clone(&self.workspace);
+        std::thread::spawn(move || {
+            // if the loop dies it starts over from the subscription file and the saved state
+            supervise(&format!("feed loop for {}", workspace.name), || {
+                read_feeds(Arc::clone(&workspace), subscriptions.clone(), &commands);
+                Ok(())
+            })
+        });
+    }
+
+    // an event from socket mode or the events api. only plain messages from people are handled,
+    // edits, joins and the like come with a subtype.
+    fn handle_event(&mut self, event: &Value) {
+        info!("handle_event(event: {:?})", event);
+        if event["type"] != "message" || !event["subtype"].is_null() || event["user"].is_null() {
+            return;
+        }
+        match serde_json::from_value::<MessageStandard>(event.clone()) {
+            Ok(message_standard) => self.handle_message(message_standard),
+            Err(e) => error!("unable to read message event: {}", e),
+        }
+    }
+
+    fn handle_message(&mut self, message_standard: MessageStandard) {
         let channel: String = message_standard.channel.clone().unwrap();
-        let user: String = message_standard.user.clone().unwrap();
-        let bot_id: &str = client
-            .start_response()
-            .slf
-            .as_ref()
-            .unwrap()
-            .id
-            .as_ref()
-            .unwrap();
-
-        let text: String = message_standard.text.clone().unwrap();
-
-        if channel == SlackChannel::Library.to_string()
-            || channel == SlackChannel::BotSpam.to_string()
-        {
-            info!("recognized message from {}", channel);
-
-            if text.starts_with("!put ") {
-                info!("matched !put");
-                parse_put(message_standard)
-            } else if text.starts_with("!last") {
-                info!("matched !last");
-                last_five(message_standard)
-            }
+        let bot_id: &str = &self.workspace.bot_id;
+
+        let text: String = message_standard.text.clone().unwrap_or_default();
+
+        if commands::dispatch(self, &message_standard) {
+            return;
         }
 
         if text.contains(bot_id) {
             info!("is a mention");
-            respond_hi(&bot_id, &text, &channel, &client);
+            respond_hi(bot_id, &text, &channel, &self.workspace);
         }
     }
 }
 
-fn respond_hi(bot_id: &str, text: &str, channel: &str, client: &RtmClient) {
+fn respond_hi(bot_id: &str, text: &str, channel: &str, workspace: &Workspace) {
     let pattern = format!("<@{}> hi", bot_id);
 
     if text.contains(&pattern) {
-        let _ = client.sender().send_message(channel, "Hi there!");
+        say_to(workspace, channel, "Hi there!");
     }
 }
 
+// connect to one workspace over rtm and handle its messages until the connection ends
+fn run_rtm(handler: &mut Handler) -> Result<(), Error> {
+    let token = handler.workspace.token.clone();
+    RtmClient::login_and_run(&token, handler).map_err(|e| format_err!("{}", e))
+}
+
 fn main() {
     env_logger::init();
     // https://github.com/emk/rust-musl-builder#making-openssl-work
-    openssl_probe::init_ssl_cert_env_vars();
+    // this mutates the environment, so it has to happen before any other threads are started.
+    unsafe { openssl_probe::init_openssl_env_vars() };
+
+    let transport = match Transport::from_env() {
+        Ok(transport) => transport,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let token = utils::get_slack_token_from_env_var();
+    let entries = match workspace::load_workspace_entries() {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("unable to load workspaces: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // load everything up front so that a mistake in any workspace stops the bot before it starts
+    let mut handlers = Vec::new();
+    for entry in &entries {
+        let loaded = entry.load().and_then(|(workspace, subscriptions)| {
+            transport.check(&workspace)?;
+            Ok(Handler::new(Arc::new(workspace), subscriptions))
+        });
+        match loaded {
+            Ok(handler) => handlers.push(handler),
+            Err(e) => {
+                eprintln!("unable to load workspace {}: {}", entry.name, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    info!(
+        "connecting to {} workspaces with {:?}",
+        handlers.len(),
+        transport
+    );
+    let mut threads = Vec::new();
+    if transport == Transport::Rtm {
+        // the other transports get slash commands along with events, rtm needs them over http, for
+        // the workspaces set up for it
+        let mut command_handlers: Vec<Handler> = handlers
+            .iter()
+            .filter(|h| h.workspace.signing_secret.is_some())
+            .map(Handler::commands_only)
+            .collect();
+        if !command_handlers.is_empty() {
+            threads.push(std::thread::spawn(move || {
+                supervise("slash command server", || {
+                    events_api::serve(&mut command_handlers, false)
+                })
+            }));
+        }
+    }
+    threads.extend(match transport {
+        Transport::Http => vec![std::thread::spawn(move || {
+            supervise("events api server", || {
+                events_api::serve(&mut handlers, true)
+            })
+        })],
+        // the websocket transports need a connection per workspace
+        Transport::Rtm | Transport::SocketMode => handlers
+            .into_iter()
+            .map(|mut handler| {
+                std::thread::spawn(move || {
+                    let name = format!("{:?} connection to {}", transport, handler.workspace.name);
+                    supervise(&name, || match transport {
+                        Transport::SocketMode => socket_mode::run(&mut handler),
+                        _ => run_rtm(&mut handler),
+                    })
+                })
+            })
+            .collect::<Vec<_>>(),
+    });
 
-    let mut handler = Handler;
-    let r = RtmClient::login_and_run(&token, &mut handler);
-    match r {
-        Ok(_) => {}
-        Err(err) => panic!("Error: {}", err),
+    // the supervisors never give up, so this only returns if one of them panics
+    for thread in threads {
+        let _ = thread.join();
     }
+    std::process::exit(1);
 }
@@ -0,0 +1,94 @@
+//! what a page says about itself, for links saved to the library
+//!
+//! the title and description come from the page's open graph tags when it has them, and its
+//! <title> and description meta tag otherwise.
+use crate::utils::{html_tags, strip_html};
+use failure::Error;
+use log::debug;
+use regex::Regex;
+use reqwest::blocking::Client;
+use reqwest::header::CONTENT_TYPE;
+use std::io::Read;
+use std::time::Duration;
+use url::Url;
+
+// everything we need is in the <head>, which is rarely anywhere near this big
+const MAX_PAGE_BYTES: u64 = 512 * 1024;
+
+#[derive(Debug, Clone, Default)]
+pub struct PageMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    // open graph's og:image and og:site_name
+    pub image: Option<String>,
+    pub site_name: Option<String>,
+    pub canonical_url: Option<String>,
+}
+
+// fetch a page and read its metadata. pages that aren't html have none.
+pub fn fetch_metadata(url: &Url) -> Result<PageMetadata, Error> {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(10))
+        .user_agent(concat!("rusty-slackbot/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let res = client.get(url.as_str()).send()?.error_for_status()?;
+
+    let is_html = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|t| t.contains("html"));
+    if !is_html {
+        debug!("{} isn't html, it has no metadata", url);
+        return Ok(PageMetadata::default());
+    }
+
+    // the page is read as utf-8 whatever it says it is, which is right often enough for titles
+    let mut body = Vec::new();
+    res.take(MAX_PAGE_BYTES).read_to_end(&mut body)?;
+    Ok(parse_metadata(url, &String::from_utf8_lossy(&body)))
+}
+
+fn parse_metadata(page_url: &Url, html: &str) -> PageMetadata {
+    // <meta> tags by their property (open graph) or name (everything else)
+    let meta = html_tags(html, "meta");
+    let content = |key: &str| {
+        meta.iter()
+            .find(|attrs| {
+                attrs
+                    .iter()
+                    .any(|(k, v)| (k == "property" || k == "name") && v.eq_ignore_ascii_case(key))
+            })
+            .and_then(|attrs| attrs.iter().find(|(k, _)| k == "content"))
+            .map(|(_, v)| strip_html(v))
+            .filter(|v| !v.is_empty())
+    };
+    // relative urls are resolved against the page
+    let resolve = |href: String| page_url.join(href.trim()).ok().map(String::from);
+
+    let canonical = html_tags(html, "link")
+        .into_iter()
+        .find(|attrs| {
+            attrs
+                .iter()
+                .any(|(k, v)| k == "rel" && v.eq_ignore_ascii_case("canonical"))
+        })
+        .and_then(|attrs| attrs.into_iter().find(|(k, _)| k == "href"))
+        .map(|(_, href)| href);
+
+    PageMetadata {
+        title: content("og:title").or_else(|| title_tag(html)),
+        description: content("og:description").or_else(|| content("description")),
+        image: content("og:image").and_then(resolve),
+        site_name: content("og:site_name"),
+        canonical_url: canonical.or_else(|| content("og:url")).and_then(resolve),
+    }
+}
+
+fn title_tag(html: &str) -> Option<String> {
+    let title_re = Regex::new(r"(?is)<title\b[^>]*>(.*?)</title>").unwrap();
+    title_re
+        .captures(html)
+        .map(|cap| strip_html(&cap[1]))
+        .filter(|title| !title.is_empty())
+}
@@ -1,26 +1,60 @@
-//! rss and atom readers
+//! rss, atom and json feed readers
+//! the format of a feed can be given up front or sniffed from the root element of the document.
 //! useful for rss debug: http://lorem-rss.herokuapp.com/feed?unit=minute&interval=60
+use crate::blocks::{article_blocks, article_text};
+use crate::config::Subscriptions;
+use crate::digest::{Digests, Schedule};
+use crate::fetch::Fetcher;
+use crate::filter::Filter;
+use crate::jsonfeed::{JsonFeed, JsonItem};
+use crate::state::StateStore;
+use crate::utils::{backoff, bot_say, post_blocks, post_reply, reply, ReplyTo};
+use crate::workspace::Workspace;
 use crate::SlackChannel;
 use atom_syndication::{Entry, Feed as AtomFeed};
+use chrono::{DateTime, Utc};
 use failure::Error;
-use linked_hash_set::LinkedHashSet;
+use linked_hash_map::LinkedHashMap;
 use log::{debug, error, info};
+use prettytable::{format, Table};
 use rss::{Channel, Item};
-use std::{thread, time::Duration};
+use serde::{Deserialize, Serialize};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
 
 #[derive(Debug, Clone)]
 pub struct Title(String);
 
 #[derive(Debug)]
-struct ArticleUrl(String);
+pub struct ArticleUrl(String);
 
+// what makes an article unique within its feed. titles get edited and aren't unique, so this is
+// the rss guid or atom id where there is one, then the link, and only then the title.
 #[derive(Debug, Clone)]
+pub struct ArticleId(String);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct FeedUrl(String);
 
 #[derive(Debug)]
 pub struct Article {
-    title: Title,
-    url: ArticleUrl,
+    pub id: ArticleId,
+    pub title: Title,
+    pub url: ArticleUrl,
+    // the title of the feed the article came from
+    pub source: Option<String>,
+    pub author: Option<String>,
+    pub published: Option<DateTime<Utc>>,
+    // only atom and json feed say when an article was last changed
+    pub updated: Option<DateTime<Utc>>,
+    // whichever of the description, summary or content the feed has. may contain html.
+    pub summary: Option<String>,
+    pub categories: Vec<String>,
 }
 
 impl Default for Title {
@@ -72,90 +106,707 @@ impl std::fmt::Display for FeedUrl {
 pub enum FeedType {
     Rss,
     Atom,
-    PythonInsider,
+    // rss 1.0, which is rdf rather than a descendant of rss 2.0
+    Rdf,
+    JsonFeed,
+    // work it out from the document each time the feed is read
+    Auto,
+}
+
+impl FeedType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FeedType::Rss => "rss",
+            FeedType::Atom => "atom",
+            FeedType::Rdf => "rdf",
+            FeedType::JsonFeed => "json",
+            FeedType::Auto => "auto",
+        }
+    }
+}
+
+impl std::str::FromStr for FeedType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FeedType, String> {
+        match s.to_lowercase().as_str() {
+            "rss" => Ok(FeedType::Rss),
+            // pythoninsider used to be its own type because of how it lays out its links, which
+            // the atom reader now handles for any feed
+            "atom" | "pythoninsider" => Ok(FeedType::Atom),
+            "rdf" => Ok(FeedType::Rdf),
+            "json" => Ok(FeedType::JsonFeed),
+            "auto" => Ok(FeedType::Auto),
+            _ => Err(format!(
+                "unknown feed type '{}', expected one of: rss, atom, rdf, json, auto",
+                s
+            )),
+        }
+    }
+}
+
+// find the name of the root element of an xml document, without any namespace prefix
+fn root_element(body: &str) -> Option<&str> {
+    let mut rest = body.trim_start_matches('\u{feff}');
+    loop {
+        rest = rest.trim_start();
+        if rest.starts_with("<?") {
+            // xml declaration or processing instruction
+            rest = &rest[rest.find("?>")? + 2..];
+        } else if rest.starts_with("<!--") {
+            rest = &rest[rest.find("-->")? + 3..];
+        } else if rest.starts_with("<!") {
+            // doctype
+            rest = &rest[rest.find('>')? + 1..];
+        } else if let Some(tag) = rest.strip_prefix('<') {
+            let end = tag.find(|c: char| c.is_whitespace() || c == '>' || c == '/')?;
+            let name = &tag[..end];
+            return name.rsplit(':').next();
+        } else {
+            return None;
+        }
+    }
+}
+
+// work out which parser a document needs from its root element, or whether it's json at all
+pub fn sniff_feed_type(body: &str) -> Option<FeedType> {
+    if body
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .starts_with('{')
+    {
+        return Some(FeedType::JsonFeed);
+    }
+
+    match root_element(body)? {
+        "rss" => Some(FeedType::Rss),
+        "RDF" => Some(FeedType::Rdf),
+        "feed" => Some(FeedType::Atom),
+        _ => None,
+    }
 }
 
 pub trait ReadFeed {
-    fn read(&self) -> Result<Vec<Article>, Error>;
+    fn read(&self, fetcher: &Fetcher) -> Result<Vec<Article>, Error>;
 }
 
-fn read_rss(feed: &Feed) -> Result<Vec<Item>, rss::Error> {
-    let FeedUrl(u) = &feed.url;
-    let channel = Channel::from_url(&u);
-    Ok(channel?.into_items())
+fn read_rss(body: &str) -> Result<Channel, rss::Error> {
+    body.parse()
 }
 
-fn get_atom_feed(url: &str) -> Result<String, reqwest::Error> {
-    let timeout = Duration::from_secs(3);
-    let client = reqwest::blocking::Client::builder()
-        .timeout(timeout)
-        .build()?;
-    let res = client.get(url).send()?;
-    let text = res.text()?;
-    Ok(text)
+fn read_atom(body: &str) -> Result<AtomFeed, Error> {
+    Ok(body.parse()?)
 }
 
-fn read_atom(feed: &Feed) -> Result<Vec<Entry>, Error> {
-    let FeedUrl(url) = &feed.url;
-    let text = get_atom_feed(url)?;
-    let atom: AtomFeed = text.parse()?;
-    Ok(atom.entries().to_vec())
+// the rss crate reads rss 1.0 documents too, it only insists on the rdf: prefix for the root
+fn read_rdf(body: &str) -> Result<Channel, rss::Error> {
+    read_rss(body)
 }
 
-impl ReadFeed for Feed {
-    fn read(&self) -> Result<Vec<Article>, Error> {
-        debug!("reading {:?} feed: {}", self.feed_type, self.url);
-        match &self.feed_type {
-            FeedType::Rss => Ok(read_rss(&self)?
+fn read_json_feed(body: &str) -> Result<JsonFeed, Error> {
+    body.parse()
+}
+
+fn non_empty(s: Option<&str>) -> Option<String> {
+    s.map(str::trim).filter(|s| !s.is_empty()).map(String::from)
+}
+
+// the link to an atom entry is its rel="alternate" link, though some feeds leave that out and
+// only have a url for an id
+fn atom_entry_url(entry: &Entry) -> &str {
+    entry
+        .links()
+        .iter()
+        .find(|l| l.rel() == "alternate")
+        .or_else(|| entry.links().first())
+        .map(|l| l.href())
+        .unwrap_or_else(|| entry.id())
+}
+
+impl Article {
+    // rss 1.0 keeps its author and date in the dublin core extension rather than in the item
+    fn from_rss(item: &Item, source: &str) -> Article {
+        let dublin_core = item.dublin_core_ext();
+        let published = item
+            .pub_date()
+            .and_then(|d| DateTime::parse_from_rfc2822(d).ok())
+            .or_else(|| {
+                dublin_core
+                    .and_then(|dc| dc.dates().first())
+                    .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+            });
+
+        Article {
+            id: ArticleId::from_rss(item),
+            url: ArticleUrl::from_str(item.link().unwrap_or_default()),
+            title: Title::from_str(item.title().unwrap_or_default()),
+            source: non_empty(Some(source)),
+            author: non_empty(item.author()).or_else(|| {
+                non_empty(dublin_core.and_then(|dc| dc.creators().first().map(String::as_str)))
+            }),
+            published: published.map(|d| d.with_timezone(&Utc)),
+            updated: None,
+            summary: non_empty(item.description()).or_else(|| non_empty(item.content())),
+            categories: item
+                .categories()
                 .iter()
-                .map(|item| Article {
-                    url: ArticleUrl::from_str(item.link().unwrap_or_default()),
-                    title: Title::from_str(item.title().unwrap_or_default()),
-                })
-                .collect()),
+                .map(|c| String::from(c.name()))
+                .collect(),
+        }
+    }
 
-            FeedType::Atom => Ok(read_atom(&self)?
+    fn from_atom(entry: &Entry, source: &str) -> Article {
+        Article {
+            id: ArticleId::from_atom(entry),
+            url: ArticleUrl::from_str(atom_entry_url(entry)),
+            title: Title::from_str(entry.title()),
+            source: non_empty(Some(source)),
+            author: non_empty(entry.authors().first().map(|a| a.name())),
+            published: Some(
+                entry
+                    .published()
+                    .unwrap_or_else(|| entry.updated())
+                    .with_timezone(&Utc),
+            ),
+            updated: Some(entry.updated().with_timezone(&Utc)),
+            summary: non_empty(entry.summary().map(|s| s.as_ref()))
+                .or_else(|| non_empty(entry.content().and_then(|c| c.value()))),
+            categories: entry
+                .categories()
                 .iter()
-                .map(|entry| Article {
-                    url: ArticleUrl::from_str(entry.id()),
-                    title: Title::from_str(entry.title()),
-                })
-                .collect()),
+                .map(|c| String::from(c.term()))
+                .collect(),
+        }
+    }
 
-            FeedType::PythonInsider => Ok(read_atom(&self)?
+    fn from_json(item: &JsonItem, source: Option<&str>) -> Article {
+        Article {
+            id: ArticleId::from_json(item),
+            url: ArticleUrl::from_str(item.link().unwrap_or_default()),
+            // titles are optional in json feed, microblog posts often don't have one
+            title: Title::from_str(
+                item.title
+                    .as_deref()
+                    .or_else(|| item.link())
+                    .unwrap_or_default(),
+            ),
+            source: non_empty(source),
+            author: non_empty(item.author()),
+            published: item
+                .date_published
+                .as_deref()
+                .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+                .map(|d| d.with_timezone(&Utc)),
+            updated: item
+                .date_modified
+                .as_deref()
+                .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+                .map(|d| d.with_timezone(&Utc)),
+            summary: non_empty(item.summary.as_deref())
+                .or_else(|| non_empty(item.content_text.as_deref()))
+                .or_else(|| non_empty(item.content_html.as_deref())),
+            categories: item.tags.clone(),
+        }
+    }
+}
+
+fn parse_articles(feed_type: &FeedType, body: &str) -> Result<Vec<Article>, Error> {
+    match feed_type {
+        FeedType::Rss => {
+            let channel = read_rss(body)?;
+            Ok(channel
+                .items()
                 .iter()
-                .map(|entry| {
-                    let url: String = entry
-                        .links()
-                        .iter()
-                        .filter(|l| l.rel() == "alternate")
-                        .map(|l| String::from(l.href()))
-                        .collect();
-                    Article {
-                        url: ArticleUrl::from_str(&url),
-                        title: Title::from_str(entry.title()),
-                    }
-                })
-                .collect()),
+                .map(|item| Article::from_rss(item, channel.title()))
+                .collect())
+        }
+
+        // rss 1.0 items don't have guids, so ArticleId::from_rss ends up using the link
+        FeedType::Rdf => {
+            let channel = read_rdf(body)?;
+            Ok(channel
+                .items()
+                .iter()
+                .map(|item| Article::from_rss(item, channel.title()))
+                .collect())
+        }
+
+        FeedType::JsonFeed => {
+            let feed = read_json_feed(body)?;
+            Ok(feed
+                .items
+                .iter()
+                .map(|item| Article::from_json(item, feed.title.as_deref()))
+                .collect())
         }
+
+        FeedType::Atom => {
+            let atom = read_atom(body)?;
+            Ok(atom
+                .entries()
+                .iter()
+                .map(|entry| Article::from_atom(entry, atom.title()))
+                .collect())
+        }
+
+        FeedType::Auto => match sniff_feed_type(body) {
+            Some(feed_type) => parse_articles(&feed_type, body),
+            None => Err(failure::format_err!("unrecognized feed format")),
+        },
+    }
+}
+
+impl ReadFeed for Feed {
+    fn read(&self, fetcher: &Fetcher) -> Result<Vec<Article>, Error> {
+        debug!("reading {:?} feed: {}", self.feed_type, self.url);
+        // nothing has changed since the last fetch, so there's nothing new to report
+        let articles =
+            fetcher.fetch_with(&self.url, |body| parse_articles(&self.feed_type, body))?;
+        Ok(articles.unwrap_or_default())
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct Feed {
     pub url: FeedUrl,
+    // what to call the feed in posts, if not the title the feed gives itself
+    pub name: Option<String>,
     pub feed_type: FeedType,
-    pub previous_titles: LinkedHashSet<String>,
+    // the articles we've already seen, by ArticleId, oldest first
+    pub seen: LinkedHashMap<String, SeenArticle>,
+    // where articles get posted. an article can go to any number of these.
+    pub routes: Vec<Route>,
+    // how many seen ids to remember before the oldest are dropped
+    pub retain: usize,
+    // None until the feed has been read successfully at least once
+    pub last_poll: Option<DateTime<Utc>>,
+    // how long to wait between reads of this feed
+    pub interval: Duration,
+    pub next_poll: Instant,
+    // consecutive failed reads, reset on the next successful one
+    pub failures: u32,
+    // whether #botspam has been told this feed is broken
+    pub failure_notified: bool,
+    // which articles get posted at all, everything by default. routes can narrow it further.
+    pub filter: Filter,
+    // post articles in a digest on this schedule rather than as they come in
+    pub digest: Option<Schedule>,
+}
+
+// what we remember about an article once it's been seen, enough to tell when it changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeenArticle {
+    pub title: String,
+    pub updated: Option<DateTime<Utc>>,
+    // where the article was posted, so that updates can be replied to in a thread
+    #[serde(default)]
+    pub posts: Vec<Post>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Post {
+    pub channel: String,
+    pub ts: String,
+}
+
+impl SeenArticle {
+    fn from_article(article: &Article) -> SeenArticle {
+        SeenArticle {
+            title: article.title.to_string(),
+            updated: article.updated,
+            posts: Vec::new(),
+        }
+    }
+
+    // an article has changed if its updated timestamp moved forward or its title changed. only
+    // some feeds say when an article was updated.
+    fn is_updated_by(&self, article: &Article) -> bool {
+        let newer = match (self.updated, article.updated) {
+            (Some(before), Some(after)) => after > before,
+            _ => false,
+        };
+        let retitled = self.title != article.title.to_string();
+        newer || retitled
+    }
+}
+
+// a channel a feed posts to, and which of the feed's articles it wants
+#[derive(Clone, Debug)]
+pub struct Route {
     pub channel: SlackChannel,
+    pub filter: Filter,
+}
+
+impl Route {
+    pub fn new(channel: SlackChannel) -> Route {
+        Route {
+            channel,
+            filter: Filter::default(),
+        }
+    }
 }
 
+pub const DEFAULT_RETAIN: usize = 200;
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(300);
+// upper bound on how many feeds are read at the same time
+const MAX_WORKERS: usize = 4;
+// a broken feed is retried less and less often, but at least this often
+const MAX_BACKOFF: Duration = Duration::from_secs(6 * 60 * 60);
+// how many failures in a row before we complain about a feed in #botspam
+const FAILURE_NOTICE_THRESHOLD: u32 = 5;
+
 impl Feed {
+    // like "#rust, #botspam", for telling people where a feed goes
+    pub fn channel_names(&self) -> String {
+        let names: Vec<String> = self
+            .routes
+            .iter()
+            .map(|r| format!("#{}", r.channel.name()))
+            .collect();
+        names.join(", ")
+    }
+
+    // the channels an article should be posted to. a channel with more than one matching route
+    // is only listed once.
+    pub fn channels_for(&self, article: &Article) -> Vec<SlackChannel> {
+        let mut channels: Vec<SlackChannel> = Vec::new();
+        if !self.filter.matches(article) {
+            return channels;
+        }
+        for route in &self.routes {
+            if !channels.contains(&route.channel) && route.filter.matches(article) {
+                channels.push(route.channel.clone());
+            }
+        }
+        channels
+    }
+
+    // the configured name, then the feed's own title, then just the host it lives on
+    pub fn display_name(&self, article: &Article) -> String {
+        self.name
+            .clone()
+            .or_else(|| article.source.clone())
+            .or_else(|| {
+                url::Url::parse(&self.url.to_string())
+                    .ok()
+                    .and_then(|u| u.host_str().map(String::from))
+            })
+            .unwrap_or_else(|| self.url.to_string())
+    }
+
     pub fn new(url: &str, feed_type: FeedType, channel: SlackChannel) -> Feed {
         Feed {
             url: FeedUrl::from_str(url),
+            name: None,
             feed_type,
-            previous_titles: LinkedHashSet::new(),
-            channel,
+            seen: LinkedHashMap::new(),
+            routes: vec![Route::new(channel)],
+            retain: DEFAULT_RETAIN,
+            last_poll: None,
+            interval: DEFAULT_INTERVAL,
+            next_poll: Instant::now(),
+            failures: 0,
+            failure_notified: false,
+            filter: Filter::default(),
+            digest: None,
+        }
+    }
+}
+
+// work out what kind of feed a body is, making sure it actually parses as one
+pub fn read_feed_body(url: &str, body: &str) -> Result<(FeedType, Vec<Article>), Error> {
+    let feed_type = sniff_feed_type(body)
+        .ok_or_else(|| failure::format_err!("{} doesn't look like a feed", url))?;
+    let articles = parse_articles(&feed_type, body)?;
+    Ok((feed_type, articles))
+}
+
+// fetch a url once and work out what kind of feed it is, making sure it actually parses as one
+pub fn detect_feed(url: &str, channel: SlackChannel) -> Result<Feed, Error> {
+    Fetcher::new()
+        .fetch_with(&FeedUrl::from_str(url), |body| {
+            feed_from_body(url, body, channel)
+        })?
+        .ok_or_else(|| failure::format_err!("{} returned no content", url))
+}
+
+// the same for a body that's already been fetched
+pub fn feed_from_body(url: &str, body: &str, channel: SlackChannel) -> Result<Feed, Error> {
+    let (feed_type, articles) = read_feed_body(url, body)?;
+    info!(
+        "detected {} as {} with {} articles",
+        url,
+        feed_type.name(),
+        articles.len()
+    );
+    Ok(Feed::new(url, feed_type, channel))
+}
+
+// changes to the running feed loop, sent from the !feed and /feeds commands. `reply_to` is where
+// the command's answer goes.
+#[derive(Debug)]
+pub enum FeedCommand {
+    Add { feed: Box<Feed>, reply_to: ReplyTo },
+    Remove { url: FeedUrl, reply_to: ReplyTo },
+    List { reply_to: ReplyTo },
+}
+
+fn handle_feed_command(
+    command: FeedCommand,
+    all_feeds: &mut Vec<Feed>,
+    store: &mut StateStore,
+    fetcher: &Fetcher,
+    workspace: &Workspace,
+) {
+    info!(
+        "handling feed command for {}: {:?}",
+        workspace.name, command
+    );
+    match command {
+        FeedCommand::Add { feed, reply_to } => {
+            let msg = if all_feeds.iter().any(|f| f.url == feed.url) {
+                format!("already subscribed to {}", feed.url)
+            } else {
+                let msg = format!(
+                    "subscribed to {} ({}) in {}",
+                    feed.url,
+                    feed.feed_type.name(),
+                    feed.channel_names()
+                );
+                all_feeds.push(*feed);
+                msg
+            };
+            reply(workspace, &reply_to, &msg)
+        }
+        FeedCommand::Remove { url, reply_to } => {
+            let before = all_feeds.len();
+            all_feeds.retain(|f| f.url != url);
+            // forget what we've seen so that adding the feed again starts from scratch
+            store.remove(&url);
+            fetcher.forget(&url);
+            let msg = if all_feeds.len() < before {
+                format!("unsubscribed from {}", url)
+            } else {
+                format!("not subscribed to {}", url)
+            };
+            reply(workspace, &reply_to, &msg)
+        }
+        FeedCommand::List { reply_to } => {
+            if all_feeds.is_empty() {
+                reply(workspace, &reply_to, "no feeds!");
+                return;
+            }
+
+            let mut table = Table::new();
+            table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+            table.set_titles(row!["type", "channel", "url"]);
+            for feed in all_feeds.iter() {
+                table.add_row(row![feed.feed_type.name(), feed.channel_names(), feed.url]);
+            }
+            reply(workspace, &reply_to, &table.to_string())
+        }
+    }
+}
+
+// wait until `deadline` or until a feed command comes in, whichever is first. returning early on
+// a command lets the caller work out a new schedule, since the command may have added a feed.
+fn wait_for_commands(
+    deadline: Instant,
+    commands: &Receiver<FeedCommand>,
+    all_feeds: &mut Vec<Feed>,
+    store: &mut StateStore,
+    fetcher: &Fetcher,
+    workspace: &Workspace,
+) {
+    let timeout = deadline.saturating_duration_since(Instant::now());
+    match commands.recv_timeout(timeout) {
+        Ok(command) => handle_feed_command(command, all_feeds, store, fetcher, workspace),
+        Err(RecvTimeoutError::Timeout) => {}
+        Err(RecvTimeoutError::Disconnected) => thread::sleep(timeout),
+    }
+}
+
+// read the feeds at the given indexes concurrently, using at most MAX_WORKERS threads
+fn read_due_feeds(
+    all_feeds: &[Feed],
+    due: Vec<usize>,
+    fetcher: &Fetcher,
+) -> Vec<(usize, Result<Vec<Article>, Error>)> {
+    let workers = MAX_WORKERS.min(due.len());
+    let queue = Mutex::new(due.into_iter());
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        // take the lock only long enough to grab the next index
+                        let next = queue.lock().unwrap().next();
+                        match next {
+                            Some(idx) => {
+                                // a feed that panics has failed like any other broken feed,
+                                // rather than taking the whole loop down with it
+                                let read =
+                                    catch_unwind(AssertUnwindSafe(|| all_feeds[idx].read(fetcher)))
+                                        .unwrap_or_else(|_| Err(failure::format_err!("panicked")));
+                                results.push((idx, read));
+                            }
+                            None => return results,
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+fn record_failure(feed: &mut Feed, e: &Error, workspace: &Workspace) {
+    feed.failures += 1;
+    let delay = backoff(feed.interval, feed.failures, MAX_BACKOFF);
+    feed.next_poll = Instant::now() + delay;
+    error!(
+        "{} failed {} times in a row, retrying in {}s: {}",
+        feed.url,
+        feed.failures,
+        delay.as_secs(),
+        e
+    );
+
+    if feed.failures >= FAILURE_NOTICE_THRESHOLD && !feed.failure_notified {
+        let msg = format!(
+            "feed {} has failed {} times in a row: {}",
+            feed.url, feed.failures, e
+        );
+        bot_say(workspace, &SlackChannel::bot_spam(), &msg);
+        feed.failure_notified = true;
+    }
+}
+
+fn record_success(feed: &mut Feed, workspace: &Workspace) {
+    if feed.failure_notified {
+        let msg = format!(
+            "feed {} is working again after {} failures",
+            feed.url, feed.failures
+        );
+        bot_say(workspace, &SlackChannel::bot_spam(), &msg);
+    }
+    feed.failures = 0;
+    feed.failure_notified = false;
+}
+
+// compare what we just read against what we've seen before and post anything new
+fn post_new_articles(
+    feed: &mut Feed,
+    articles: Vec<Article>,
+    digests: &mut Digests,
+    workspace: &Workspace,
+    client: &reqwest::blocking::Client,
+) {
+    let token = &workspace.token;
+    info!(
+        "got {} articles from {:?} {}",
+        articles.len(),
+        feed.feed_type,
+        feed.url
+    );
+
+    // initial run, we've never seen this feed before so just remember what's in it now
+    if feed.last_poll.is_none() {
+        for article in &articles {
+            let ArticleId(id) = &article.id;
+            feed.seen
+                .insert(id.clone(), SeenArticle::from_article(article));
+        }
+        feed.last_poll = Some(Utc::now());
+        return;
+    }
+    feed.last_poll = Some(Utc::now());
+
+    for article in articles {
+        let ArticleId(id) = &article.id;
+
+        if let Some(seen) = feed.seen.get_mut(id) {
+            if seen.is_updated_by(&article) {
+                info!("article updated: {} ({})", article.title, id);
+                reply_with_update(seen, &article, client, token);
+                seen.title = article.title.to_string();
+                seen.updated = article.updated;
+            }
+            continue;
+        }
+
+        info!("found new article: {} ({})", article.title, id);
+        let mut seen = SeenArticle::from_article(&article);
+
+        let targets = feed.channels_for(&article);
+        if targets.is_empty() {
+            info!("filtered out: {}", article.title);
+            feed.seen.insert(id.to_string(), seen);
+            continue;
+        }
+
+        let text = article_text(&article);
+        let blocks = article_blocks(&feed.display_name(&article), &article);
+        for channel in targets {
+            if digests.queue(feed, &channel, &article) {
+                continue;
+            }
+
+            let chan_id = match workspace.channels.id(&channel) {
+                Some(chan_id) => chan_id,
+                None => {
+                    error!("no #{} channel, not posting {}", channel, article.url);
+                    continue;
+                }
+            };
+            info!("sending channel {}: {}", &chan_id, &text);
+            match post_blocks(client, token, &chan_id, &text, &blocks) {
+                Ok(res) => {
+                    if let Some(ts) = res["ts"].as_str() {
+                        seen.posts.push(Post {
+                            channel: chan_id,
+                            ts: String::from(ts),
+                        });
+                    }
+                }
+                Err(e) => error!("unable to post {} to {}: {}", article.url, chan_id, e),
+            }
+        }
+        feed.seen.insert(id.to_string(), seen);
+    }
+
+    while feed.seen.len() > feed.retain {
+        info!("popping: {:?}", feed.seen.pop_front());
+    }
+}
+
+// let each channel an article was posted to know it's changed, under the original post
+fn reply_with_update(
+    seen: &SeenArticle,
+    article: &Article,
+    client: &reqwest::blocking::Client,
+    token: &str,
+) {
+    // articles seen but never posted, on the first read or because they were filtered out, have no
+    // thread to reply in
+    if seen.posts.is_empty() {
+        info!(
+            "{} was updated but there's no post of it to reply to",
+            article.url
+        );
+        return;
+    }
+    let text = format!("updated: {}", article_text(article));
+    for post in &seen.posts {
+        info!("replying to {} in {}: {}", post.ts, post.channel, text);
+        if let Err(e) = post_reply(client, token, &post.channel, &post.ts, &text) {
+            error!("unable to reply to {} in {}: {}", post.ts, post.channel, e);
         }
     }
 }
@@ -166,6 +817,42 @@ impl ArticleUrl {
     }
 }
 
+impl ArticleId {
+    fn from_rss(item: &Item) -> ArticleId {
+        let id = item
+            .guid()
+            .map(|guid| guid.value())
+            .filter(|guid| !guid.is_empty())
+            .or_else(|| item.link().filter(|link| !link.is_empty()))
+            .or_else(|| item.title())
+            .unwrap_or_default();
+        ArticleId(String::from(id))
+    }
+
+    fn from_atom(entry: &Entry) -> ArticleId {
+        let id = Some(entry.id())
+            .filter(|id| !id.is_empty())
+            .or_else(|| {
+                entry
+                    .links()
+                    .iter()
+                    .find(|l| l.rel() == "alternate")
+                    .map(|l| l.href())
+            })
+            .unwrap_or_else(|| entry.title());
+        ArticleId(String::from(id))
+    }
+
+    fn from_json(item: &JsonItem) -> ArticleId {
+        let id = item
+            .id()
+            .or_else(|| item.link().map(String::from))
+            .or_else(|| item.title.clone())
+            .unwrap_or_default();
+        ArticleId(id)
+    }
+}
+
 impl Title {
     fn from_str(s: &str) -> Title {
         Title(String::from(s))
@@ -178,111 +865,333 @@ impl FeedUrl {
     }
 }
 
-pub fn read_feeds(token: String) {
-    let sleep_duration = Duration::from_secs(300);
-    let titles_to_retain = 200;
+pub fn read_feeds(
+    workspace: Arc<Workspace>,
+    subscriptions: Subscriptions,
+    commands: &Receiver<FeedCommand>,
+) {
     let client = slack_api::sync::requests::default_client().unwrap();
+    let fetcher = Fetcher::new();
+    let mut all_feeds = subscriptions.feeds;
+    let mut digests = Digests::new(subscriptions.digests);
 
-    let rss_feeds = vec![
-        // (
-        //     "https://lorem-rss.herokuapp.com/feed?unit=second&interval=30",
-        //     SlackChannel::BattleBots,
-        // ),
-        ("https://blog.japaric.io/index.xml", SlackChannel::Rust),
-        ("https://newrustacean.com/feed.xml", SlackChannel::Rust),
-        ("https://nercury.github.io/feed.xml", SlackChannel::Rust),
-        ("https://os.phil-opp.com/rss.xml", SlackChannel::Rust),
-        ("https://this-week-in-rust.org/rss.xml", SlackChannel::Rust),
-        ("https://aws.amazon.com/blogs/aws/feed/", SlackChannel::Aws),
-        ("https://kubernetes.io/feed.xml", SlackChannel::Kubernetes),
-    ];
-
-    let rss_feeds: Vec<Feed> = rss_feeds
-        .iter()
-        .map(|(url, chan)| Feed::new(url, FeedType::Rss, chan.clone()))
-        .collect();
-
-    let atom_feeds = vec![("https://blog.rust-lang.org/feed.xml", SlackChannel::Rust)];
-
-    let atom_feeds: Vec<Feed> = atom_feeds
-        .iter()
-        .map(|(url, chan)| Feed::new(url, FeedType::Atom, chan.clone()))
-        .collect();
-
-    let mut all_feeds = Vec::new();
-    all_feeds.extend(rss_feeds);
-    all_feeds.extend(atom_feeds);
-    all_feeds.extend(vec![Feed::new(
-        "http://feeds.feedburner.com/PythonInsider",
-        FeedType::PythonInsider,
-        SlackChannel::Python,
-    )]);
+    let mut store = StateStore::load(&workspace.state_path);
+    for feed in &mut all_feeds {
+        store.restore(feed);
+    }
+    store.restore_digests(&mut digests);
 
     // main loop
     loop {
-        for feed in &mut all_feeds {
-            // initial run
-            if feed.previous_titles.is_empty() {
-                match feed.read() {
+        let now = Instant::now();
+        let due: Vec<usize> = all_feeds
+            .iter()
+            .enumerate()
+            .filter(|(_, feed)| feed.next_poll <= now)
+            .map(|(idx, _)| idx)
+            .collect();
+
+        if !due.is_empty() {
+            debug!("{} feeds due", due.len());
+            for (idx, result) in read_due_feeds(&all_feeds, due, &fetcher) {
+                let feed = &mut all_feeds[idx];
+                feed.next_poll = Instant::now() + feed.interval;
+                match result {
                     Ok(articles) => {
-                        info!(
-                            "got {} articles from {:?} {}",
-                            articles.len(),
-                            feed.feed_type,
-                            feed.url
-                        );
-                        let titles = articles.iter().map(|article| {
-                            let Title(t) = &article.title;
-                            t
-                        });
-                        for title in titles {
-                            feed.previous_titles.insert(title.clone());
-                        }
+                        record_success(feed, &workspace);
+                        post_new_articles(feed, articles, &mut digests, &workspace, &client);
+                        store.update(feed);
                     }
-                    Err(e) => error!("{}", e),
+                    Err(e) => record_failure(feed, &e, &workspace),
                 }
-                continue;
             }
 
-            while feed.previous_titles.len() > titles_to_retain {
-                info!("popping: {:?}", feed.previous_titles.pop_front());
+            store.update_digests(&digests);
+            if let Err(e) = store.save() {
+                error!("unable to save feed state: {}", e);
             }
+        }
 
-            let chan_id = feed.channel.to_string();
-            match feed.read() {
-                Ok(articles) => {
-                    info!(
-                        "got {} articles from {:?} {}",
-                        &articles.len(),
-                        feed.feed_type,
-                        feed.url
-                    );
-                    for article in articles {
-                        let Title(title) = &article.title;
-
-                        if !feed.previous_titles.contains(title) {
-                            info!("found new title: {}", title);
-                            feed.previous_titles.insert(title.to_string());
-
-                            let text = format!("<{}|{}>", article.url, article.title);
-                            info!("sending channel {}: {}", &chan_id, &text);
-                            let msg = slack_api::sync::chat::PostMessageRequest {
-                                channel: &chan_id,
-                                text: &text,
-                                as_user: Some(true),
-                                ..Default::default()
-                            };
-
-                            debug!(
-                                "{:?}",
-                                slack_api::sync::chat::post_message(&client, &token, &msg)
-                            );
-                        }
-                    }
-                }
-                Err(e) => error!("{}", e),
+        if digests
+            .next_run()
+            .is_some_and(|next_run| next_run <= Utc::now())
+        {
+            digests.post_due(&client, &workspace);
+            store.update_digests(&digests);
+            if let Err(e) = store.save() {
+                error!("unable to save feed state: {}", e);
+            }
+        }
+
+        // sleep until the next feed or digest is due, or for a while if there aren't any feeds
+        let next_digest = digests.next_run().map(|next_run| {
+            let until = (next_run - Utc::now()).to_std().unwrap_or_default();
+            Instant::now() + until
+        });
+        let next_poll = all_feeds
+            .iter()
+            .map(|feed| feed.next_poll)
+            .chain(next_digest)
+            .min()
+            .unwrap_or_else(|| Instant::now() + DEFAULT_INTERVAL);
+        wait_for_commands(
+            next_poll,
+            commands,
+            &mut all_feeds,
+            &mut store,
+            &fetcher,
+            &workspace,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn article(title: &str, updated: Option<DateTime<Utc>>) -> Article {
+        Article {
+            id: ArticleId(String::from("id")),
+            title: Title(String::from(title)),
+            url: ArticleUrl::from_str("https://example.com/article"),
+            source: None,
+            author: None,
+            published: None,
+            updated,
+            summary: None,
+            categories: Vec::new(),
+        }
+    }
+
+    fn seen(title: &str, updated: Option<DateTime<Utc>>) -> SeenArticle {
+        SeenArticle {
+            title: String::from(title),
+            updated,
+            posts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn newer_updated_is_an_update() {
+        let before = at("2020-01-01T12:00:00Z");
+        let after = at("2020-01-02T12:00:00Z");
+        let seen = seen("title", Some(before));
+        assert!(seen.is_updated_by(&article("title", Some(after))));
+        assert!(!seen.is_updated_by(&article("title", Some(before))));
+        assert!(!seen.is_updated_by(&article("title", Some(at("2019-12-31T12:00:00Z")))));
+    }
+
+    #[test]
+    fn retitle_is_an_update() {
+        let seen = seen("title", None);
+        assert!(seen.is_updated_by(&article("new title", None)));
+        assert!(!seen.is_updated_by(&article("title", None)));
+    }
+
+    #[test]
+    fn missing_fields_are_not_updates() {
+        let when = at("2020-01-01T12:00:00Z");
+        // the feed stopped saying when it was updated
+        assert!(!seen("title", Some(when)).is_updated_by(&article("title", None)));
+        // the feed started saying when it was updated
+        assert!(!seen("title", None).is_updated_by(&article("title", Some(when))));
+    }
+
+    #[test]
+    fn root_element_skips_the_prolog() {
+        assert_eq!(root_element("<rss version=\"2.0\">"), Some("rss"));
+        assert_eq!(root_element("\u{feff}<feed>"), Some("feed"));
+        assert_eq!(
+            root_element("<?xml version=\"1.0\"?>\n<?xml-stylesheet href=\"s.xsl\"?>\n<rss/>"),
+            Some("rss")
+        );
+        assert_eq!(
+            root_element("<!-- <rss> -->\n<!DOCTYPE feed>\n<feed xmlns=\"x\">"),
+            Some("feed")
+        );
+        assert_eq!(root_element("<rdf:RDF xmlns:rdf=\"x\">"), Some("RDF"));
+        assert_eq!(root_element("not xml"), None);
+        assert_eq!(root_element("<!-- never closed"), None);
+    }
+
+    #[test]
+    fn sniff_feed_types() {
+        let sniff = sniff_feed_type;
+        assert!(matches!(
+            sniff("<?xml version=\"1.0\"?><rss>"),
+            Some(FeedType::Rss)
+        ));
+        assert!(matches!(sniff("<feed>"), Some(FeedType::Atom)));
+        assert!(matches!(sniff("<rdf:RDF>"), Some(FeedType::Rdf)));
+        assert!(matches!(
+            sniff("\u{feff}\n  {\"version\": \"\"}"),
+            Some(FeedType::JsonFeed)
+        ));
+        assert!(sniff("<!DOCTYPE html><html>").is_none());
+        assert!(sniff("").is_none());
+    }
+
+    const RSS_1: &str = r#"<?xml version="1.0"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+    xmlns="http://purl.org/rss/1.0/" xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <channel rdf:about="https://example.com/">
+    <title>Example</title>
+    <link>https://example.com/</link>
+    <description>an rss 1.0 feed</description>
+  </channel>
+  <item rdf:about="https://example.com/one">
+    <title>One</title>
+    <link>https://example.com/one</link>
+    <description>the first one</description>
+    <dc:creator>Someone</dc:creator>
+    <dc:date>2020-01-02T03:04:05Z</dc:date>
+  </item>
+</rdf:RDF>"#;
+
+    #[test]
+    fn parse_rss_1() {
+        let articles = parse_articles(&FeedType::Rdf, RSS_1).unwrap();
+        assert_eq!(articles.len(), 1);
+        let article = &articles[0];
+        // no guid, so the link is the id
+        assert_eq!(article.id.0, "https://example.com/one");
+        assert_eq!(article.title.to_string(), "One");
+        assert_eq!(article.url.to_string(), "https://example.com/one");
+        assert_eq!(article.source.as_deref(), Some("Example"));
+        assert_eq!(article.author.as_deref(), Some("Someone"));
+        assert_eq!(article.published, Some(at("2020-01-02T03:04:05Z")));
+        assert_eq!(article.summary.as_deref(), Some("the first one"));
+    }
+
+    const JSON_FEED: &str = r#"{
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": "Example",
+        "items": [
+            {
+                "id": 1,
+                "url": "https://example.com/one",
+                "title": "One",
+                "content_html": "<p>the first one</p>",
+                "date_published": "2020-01-02T03:04:05Z",
+                "date_modified": "2020-01-03T03:04:05Z",
+                "authors": [{"name": "Someone"}],
+                "tags": ["rust"]
+            },
+            {
+                "id": "",
+                "external_url": "https://example.org/elsewhere",
+                "content_text": "no title"
             }
+        ]
+    }"#;
+
+    #[test]
+    fn parse_json_feed() {
+        let articles = parse_articles(&FeedType::JsonFeed, JSON_FEED).unwrap();
+        assert_eq!(articles.len(), 2);
+
+        let one = &articles[0];
+        assert_eq!(one.id.0, "1");
+        assert_eq!(one.title.to_string(), "One");
+        assert_eq!(one.source.as_deref(), Some("Example"));
+        assert_eq!(one.author.as_deref(), Some("Someone"));
+        assert_eq!(one.published, Some(at("2020-01-02T03:04:05Z")));
+        assert_eq!(one.updated, Some(at("2020-01-03T03:04:05Z")));
+        assert_eq!(one.summary.as_deref(), Some("<p>the first one</p>"));
+        assert_eq!(one.categories, vec!["rust"]);
+
+        // without an id or title the link stands in for both
+        let two = &articles[1];
+        assert_eq!(two.id.0, "https://example.org/elsewhere");
+        assert_eq!(two.title.to_string(), "https://example.org/elsewhere");
+        assert_eq!(two.summary.as_deref(), Some("no title"));
+    }
+
+    #[test]
+    fn json_feed_versions() {
+        let old = JSON_FEED.replace("version/1.1", "version/1");
+        assert_eq!(parse_articles(&FeedType::JsonFeed, &old).unwrap().len(), 2);
+        let unknown = JSON_FEED.replace("https://jsonfeed.org/version/1.1", "2");
+        assert!(parse_articles(&FeedType::JsonFeed, &unknown).is_err());
+    }
+
+    fn keywords(include: &[&str], exclude: &[&str]) -> Filter {
+        let strings = |words: &[&str]| words.iter().map(|w| String::from(*w)).collect::<Vec<_>>();
+        Filter::new(
+            (&strings(include), &strings(exclude)),
+            (&[], &[]),
+            (&[], &[]),
+        )
+        .unwrap()
+    }
+
+    fn route(channel: &str, filter: Filter) -> Route {
+        Route {
+            channel: channel.parse().unwrap(),
+            filter,
         }
-        thread::sleep(sleep_duration);
+    }
+
+    fn channels_for(feed: &Feed, title: &str) -> Vec<String> {
+        feed.channels_for(&article(title, None))
+            .iter()
+            .map(|c| c.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn routes_pick_channels() {
+        let mut feed = Feed::new(
+            "https://example.com/feed.xml",
+            FeedType::Rss,
+            SlackChannel::bot_spam(),
+        );
+        feed.routes = vec![
+            route("general", Filter::default()),
+            route("rust", keywords(&["rust"], &[])),
+            route("python", keywords(&["python"], &[])),
+        ];
+        assert_eq!(
+            channels_for(&feed, "rust and python"),
+            vec!["general", "rust", "python"]
+        );
+        assert_eq!(channels_for(&feed, "rust"), vec!["general", "rust"]);
+        assert_eq!(channels_for(&feed, "go"), vec!["general"]);
+    }
+
+    #[test]
+    fn feed_filter_comes_first() {
+        let mut feed = Feed::new(
+            "https://example.com/feed.xml",
+            FeedType::Rss,
+            SlackChannel::bot_spam(),
+        );
+        feed.filter = keywords(&[], &["sponsored"]);
+        feed.routes = vec![
+            route("general", Filter::default()),
+            route("rust", keywords(&["rust"], &[])),
+        ];
+        assert!(channels_for(&feed, "sponsored: learn rust").is_empty());
+        assert_eq!(channels_for(&feed, "learn rust"), vec!["general", "rust"]);
+    }
+
+    #[test]
+    fn channels_listed_once() {
+        let mut feed = Feed::new(
+            "https://example.com/feed.xml",
+            FeedType::Rss,
+            SlackChannel::bot_spam(),
+        );
+        feed.routes = vec![
+            route("rust", keywords(&["async"], &[])),
+            route("general", keywords(&[], &["async"])),
+            route("rust", keywords(&["tokio"], &[])),
+        ];
+        assert_eq!(channels_for(&feed, "async tokio"), vec!["rust"]);
+        assert_eq!(channels_for(&feed, "tokio"), vec!["general", "rust"]);
     }
 }
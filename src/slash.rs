@@ -0,0 +1,142 @@
+//! slash commands, posted to us over http like events are, or sent over the socket in socket mode
+//!
+//! /library put, /library last, /library search and /feeds work in any channel, and only the
+//! person who used one sees the answer. /library's commands take the same arguments as the !
+//! commands of the same name.
+use crate::commands::{parse_args, Args, Command};
+use crate::library::{last_five_table, parse_put_tags, parse_tag, put, search_table};
+use crate::subscriptions::feed_command;
+use crate::utils::{reply, ReplyTo};
+use crate::workspace::Workspace;
+use crate::Handler;
+use log::{error, info};
+use std::collections::HashMap;
+
+// the ! commands that also work as /library <command>
+const LIBRARY_COMMANDS: &[&str] = &["put", "last", "search"];
+
+fn library_usage() -> String {
+    let usages: Vec<String> = LIBRARY_COMMANDS
+        .iter()
+        .filter_map(|name| Command::find(name))
+        .map(|command| command.usage("/library "))
+        .collect();
+    format!("usage: {}", usages.join(" | "))
+}
+
+// which of the library commands the text is for, and its arguments
+fn parse_library_command(text: &str) -> Result<(&'static Command, Args), String> {
+    let text = text.trim();
+    let (name, arg_text) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    match Command::find(name) {
+        Some(command) if LIBRARY_COMMANDS.contains(&name) => {
+            Ok((command, command.parse(arg_text, "/library ")?))
+        }
+        _ => Err(library_usage()),
+    }
+}
+
+fn library_command(text: &str, user: &str, workspace: &Workspace) -> Result<String, String> {
+    let (command, args) = parse_library_command(text)?;
+    match command.name {
+        "put" => {
+            let tags = parse_put_tags(&args.positional[1..])?;
+            put(&args.positional[0], &tags, user, workspace)
+        }
+        "last" => {
+            let tag = args.value("tag").map(parse_tag).transpose()?;
+            Ok(last_five_table(tag.as_deref(), workspace))
+        }
+        "search" => {
+            let terms: Vec<&str> = args.positional.iter().map(String::as_str).collect();
+            Ok(search_table(&terms, workspace))
+        }
+        _ => Err(library_usage()),
+    }
+}
+
+pub fn handle_command(handler: &Handler, form: &HashMap<String, String>) {
+    let workspace = &handler.workspace;
+    let field = |name: &str| form.get(name).map(String::as_str).unwrap_or_default();
+    let command = field("command");
+    let text = field("text");
+    let channel = field("channel_id");
+    let user = field("user_id");
+    info!("{} used {} {} in {}", user, command, text, channel);
+
+    let response_url = field("response_url");
+    if response_url.is_empty() {
+        error!("{} came without a response_url", command);
+        return;
+    }
+    let reply_to = ReplyTo::Ephemeral(String::from(response_url));
+
+    match command {
+        "/library" => {
+            let msg = library_command(text, user, workspace).unwrap_or_else(|msg| msg);
+            reply(workspace, &reply_to, &msg)
+        }
+        "/feeds" => match parse_args(text, &[]) {
+            Ok(args) => {
+                let args: Vec<&str> = args.positional.iter().map(String::as_str).collect();
+                feed_command(
+                    "/feeds",
+                    &args,
+                    channel,
+                    &reply_to,
+                    &handler.feed_commands,
+                    workspace,
+                )
+            }
+            Err(msg) => reply(workspace, &reply_to, &msg),
+        },
+        _ => reply(
+            workspace,
+            &reply_to,
+            &format!("unknown command: {}", command),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> String {
+        match parse_library_command(text) {
+            Ok(_) => panic!("parsed {}", text),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn usage_comes_from_the_commands() {
+        assert_eq!(
+            library_usage(),
+            "usage: /library put <url> [#tag ...] | /library last [--tag <tag>] | \
+             /library search <terms>"
+        );
+    }
+
+    #[test]
+    fn parse_library_commands() {
+        let (command, args) = parse_library_command(" last --tag rust").unwrap();
+        assert_eq!(command.name, "last");
+        assert_eq!(args.value("tag"), Some("rust"));
+
+        let (command, args) = parse_library_command(r#"search "two words" #tag"#).unwrap();
+        assert_eq!(command.name, "search");
+        assert_eq!(args.positional, vec!["two words", "#tag"]);
+
+        assert_eq!(error("last five"), "usage: /library last [--tag <tag>]");
+        assert_eq!(error("put"), "usage: /library put <url> [#tag ...]");
+        assert!(error("last --tags=rust").starts_with("unknown flag --tags"));
+    }
+
+    #[test]
+    fn only_library_commands() {
+        for text in &["", "help", "feed list", "delete 1"] {
+            assert_eq!(error(text), library_usage());
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! socket mode, where slack sends events over a websocket that we open
+//!
+//! every message from slack comes in an envelope that has to be acknowledged, or slack will send
+//! it again. slash commands come in envelopes too, rather than over http. slack also asks for a
+//! new connection every so often, so a disconnect just means opening another one.
+use crate::slash::handle_command;
+use crate::Handler;
+use failure::{bail, format_err, Error};
+use log::{debug, info};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tungstenite::Message;
+use url::Url;
+
+// ask slack for a websocket url, using the workspace's app-level token
+fn open_connection(app_token: &str) -> Result<Url, Error> {
+    let res: Value = reqwest::blocking::Client::new()
+        .post("https://slack.com/api/apps.connections.open")
+        .bearer_auth(app_token)
+        .send()?
+        .json()?;
+
+    if res["ok"].as_bool() != Some(true) {
+        bail!("apps.connections.open failed: {}", res["error"]);
+    }
+    let url = res["url"]
+        .as_str()
+        .ok_or_else(|| format_err!("apps.connections.open didn't return a url"))?;
+    Ok(Url::parse(url)?)
+}
+
+// a slash command's fields, the same as they'd be in the form posted over http
+fn command_form(envelope: &Value) -> HashMap<String, String> {
+    envelope["payload"]
+        .as_object()
+        .map(|payload| {
+            payload
+                .iter()
+                .filter_map(|(name, value)| Some((name.clone(), value.as_str()?.to_string())))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// handle the workspace's events until the connection can't be opened or fails
+pub fn run(handler: &mut Handler) -> Result<(), Error> {
+    let app_token = handler
+        .workspace
+        .app_token
+        .clone()
+        .ok_or_else(|| format_err!("socket mode needs a SLACK_APP_TOKEN"))?;
+    handler.start_feeds();
+
+    loop {
+        let url = open_connection(&app_token)?;
+        let (mut socket, _) = tungstenite::connect(url)?;
+        info!("connected to {} with socket mode", handler.workspace.name);
+
+        loop {
+            let text = match socket.read_message()? {
+                Message::Text(text) => text,
+                Message::Close(frame) => {
+                    info!("socket mode connection closed: {:?}", frame);
+                    break;
+                }
+                _ => continue,
+            };
+            let envelope: Value = serde_json::from_str(&text)?;
+            debug!("{:?}", envelope);
+
+            // acknowledge before handling, since some commands take longer than slack will wait
+            if let Some(envelope_id) = envelope["envelope_id"].as_str() {
+                let ack = json!({ "envelope_id": envelope_id });
+                socket.write_message(Message::Text(ack.to_string()))?;
+            }
+
+            match envelope["type"].as_str() {
+                Some("events_api") => handler.handle_event(&envelope["payload"]["event"]),
+                Some("slash_commands") => handle_command(handler, &command_form(&envelope)),
+                Some("disconnect") => {
+                    info!(
+                        "slack asked {} to reconnect: {}",
+                        handler.workspace.name, envelope["reason"]
+                    );
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+}
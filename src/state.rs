@@ -0,0 +1,168 @@
+//! feed and digest state that has to survive a restart, kept in a json file
+//!
+//! each workspace has its own file. with a single workspace it's written to the path in
+//! RUSTY_STATE_PATH (default: state.json).
+use crate::digest::{Digests, SavedDigest};
+use crate::reader::{Feed, FeedUrl, SeenArticle};
+use chrono::{DateTime, Utc};
+use failure::Error;
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+const STATE_PATH_ENV_VAR: &str = "RUSTY_STATE_PATH";
+const DEFAULT_STATE_PATH: &str = "state.json";
+
+pub fn state_path() -> String {
+    std::env::var(STATE_PATH_ENV_VAR).unwrap_or_else(|_| String::from(DEFAULT_STATE_PATH))
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FeedState {
+    // oldest first, the same order as Feed::seen
+    #[serde(default)]
+    pub articles: Vec<(String, SeenArticle)>,
+    pub last_poll: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StateFile {
+    #[serde(default)]
+    feeds: HashMap<FeedUrl, FeedState>,
+    // articles waiting to go out in a digest, see Digests::saved
+    #[serde(default)]
+    digests: HashMap<String, SavedDigest>,
+}
+
+fn parse_state(json: &str) -> Result<StateFile, Error> {
+    Ok(serde_json::from_str(json)?)
+}
+
+#[derive(Debug)]
+pub struct StateStore {
+    path: String,
+    state: StateFile,
+}
+
+impl StateStore {
+    // load whatever state was saved last time. a missing file just means we're starting fresh.
+    pub fn load(path: &str) -> StateStore {
+        let state = if Path::new(path).exists() {
+            match std::fs::read_to_string(path)
+                .map_err(Error::from)
+                .and_then(|s| parse_state(&s))
+            {
+                Ok(state) => state,
+                Err(e) => {
+                    error!(
+                        "unable to load feed state from {}, starting fresh: {}",
+                        path, e
+                    );
+                    StateFile::default()
+                }
+            }
+        } else {
+            info!("no feed state at {}, starting fresh", path);
+            StateFile::default()
+        };
+
+        StateStore {
+            path: String::from(path),
+            state,
+        }
+    }
+
+    // seed a feed with what we knew about it before the restart, if anything
+    pub fn restore(&self, feed: &mut Feed) {
+        if let Some(state) = self.state.feeds.get(&feed.url) {
+            feed.seen = state.articles.iter().cloned().collect();
+            feed.last_poll = state.last_poll;
+            info!(
+                "restored {} seen articles for {}, last polled {:?}",
+                feed.seen.len(),
+                feed.url,
+                state.last_poll
+            );
+        }
+    }
+
+    pub fn update(&mut self, feed: &Feed) {
+        let state = FeedState {
+            articles: feed
+                .seen
+                .iter()
+                .map(|(id, article)| (id.clone(), article.clone()))
+                .collect(),
+            last_poll: feed.last_poll,
+        };
+        self.state.feeds.insert(feed.url.clone(), state);
+    }
+
+    pub fn remove(&mut self, url: &FeedUrl) {
+        self.state.feeds.remove(url);
+    }
+
+    pub fn restore_digests(&self, digests: &mut Digests) {
+        digests.restore(&self.state.digests);
+    }
+
+    pub fn update_digests(&mut self, digests: &Digests) {
+        self.state.digests = digests.saved();
+    }
+
+    // write to a temporary file first so a crash mid-write can't leave a truncated state file
+    pub fn save(&self) -> Result<(), Error> {
+        let tmp_path = format!("{}.tmp", self.path);
+        std::fs::write(&tmp_path, serde_json::to_string(&self.state)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        debug!("saved feed state to {}", self.path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::FeedType;
+    use crate::SlackChannel;
+
+    const URL: &str = "https://example.com/feed.xml";
+
+    fn feed() -> Feed {
+        Feed::new(URL, FeedType::Rss, SlackChannel::bot_spam())
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let mut original = feed();
+        for (id, title) in &[("a", "a title"), ("b", "b title")] {
+            let seen = SeenArticle {
+                title: String::from(*title),
+                updated: None,
+                posts: Vec::new(),
+            };
+            original.seen.insert(String::from(*id), seen);
+        }
+        original.last_poll = Some("2020-01-01T00:00:00Z".parse().unwrap());
+
+        let mut store = StateStore {
+            path: String::new(),
+            state: StateFile::default(),
+        };
+        store.update(&original);
+        let json = serde_json::to_string(&store.state).unwrap();
+        let store = StateStore {
+            path: String::new(),
+            state: parse_state(&json).unwrap(),
+        };
+
+        let mut restored = feed();
+        store.restore(&mut restored);
+        let ids: Vec<&str> = restored.seen.keys().map(String::as_str).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert_eq!(restored.seen["a"].title, "a title");
+        assert_eq!(restored.last_poll, original.last_poll);
+    }
+}
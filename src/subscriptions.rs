@@ -0,0 +1,103 @@
+//! !feed and /feeds commands for changing feed subscriptions while the bot is running
+//!
+//! the url for add can be a feed or a website to look for feeds on.
+use crate::channels::ChannelDirectory;
+use crate::discovery::discover_feeds;
+use crate::reader::{FeedCommand, FeedUrl};
+use crate::utils::{parse_slack_url, reply, ReplyTo};
+use crate::workspace::Workspace;
+use crate::SlackChannel;
+use log::{error, info};
+use std::sync::mpsc::Sender;
+use url::Url;
+
+// a channel reference in a message looks like <#C8EHWNKHV|rust> or <#C8EHWNKHV>, but we'll also
+// take a plain name like #rust or rust.
+fn parse_channel(s: &str, channels: &ChannelDirectory) -> Option<SlackChannel> {
+    if s.starts_with("<#") && s.ends_with('>') {
+        let inner = &s[2..s.len() - 1];
+        let id = inner.split('|').next().unwrap_or_default();
+        return channels.channel(id);
+    }
+    let channel: SlackChannel = s.parse().ok()?;
+    channels.id(&channel).map(|_| channel)
+}
+
+fn parse_feed_url(s: &str) -> Result<Url, String> {
+    let url_string = parse_slack_url(s);
+    Url::parse(url_string).map_err(|e| format!("unable to parse as url: {} ({})", s, e))
+}
+
+// turn the arguments to !feed or /feeds into a command for the feed loop. `channel` is the id of
+// the channel the command was used in, which is where an added feed goes if no channel is named.
+pub fn feed_command(
+    name: &str,
+    args: &[&str],
+    channel: &str,
+    reply_to: &ReplyTo,
+    commands: &Sender<FeedCommand>,
+    workspace: &Workspace,
+) {
+    let channels = &workspace.channels;
+    let command = match args {
+        ["list"] => Ok(FeedCommand::List {
+            reply_to: reply_to.clone(),
+        }),
+        ["remove", url] => parse_feed_url(url).map(|url| FeedCommand::Remove {
+            url: FeedUrl::from_str(url.as_str()),
+            reply_to: reply_to.clone(),
+        }),
+        ["add", url, rest @ ..] if rest.len() <= 1 => {
+            // without an explicit channel, post to the channel the command came from
+            let target = match rest.first() {
+                Some(s) => {
+                    parse_channel(s, channels).ok_or_else(|| format!("unknown channel: {}", s))
+                }
+                None => channels
+                    .channel(channel)
+                    .ok_or_else(|| String::from("please name a channel to post to")),
+            };
+            target.and_then(|target| {
+                let url = parse_feed_url(url)?;
+                info!("looking for feeds at {}", url);
+                let mut feeds = discover_feeds(&url, target).map_err(|e| e.to_string())?;
+                let feed = feeds.remove(0);
+                if !feeds.is_empty() {
+                    let others: Vec<String> = feeds.iter().map(|f| f.url.to_string()).collect();
+                    let msg = format!(
+                        "found more than one feed at {}, using {}. the others are:\n{}",
+                        url,
+                        feed.url,
+                        others.join("\n")
+                    );
+                    reply(workspace, reply_to, &msg);
+                }
+                Ok(FeedCommand::Add {
+                    feed: Box::new(feed),
+                    reply_to: reply_to.clone(),
+                })
+            })
+        }
+        _ => Err(format!(
+            "usage: {name} add <url> [#channel] | {name} remove <url> | {name} list",
+            name = name
+        )),
+    };
+
+    match command {
+        Ok(command) => {
+            if let Err(e) = commands.send(command) {
+                error!("feed loop isn't running: {}", e);
+                reply(
+                    workspace,
+                    reply_to,
+                    "unable to reach the feed loop, try again later",
+                );
+            }
+        }
+        Err(msg) => {
+            error!("{}", msg);
+            reply(workspace, reply_to, &msg)
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! keeping connections and the feed loop running
+//!
+//! whatever runs under supervise is started again when it returns, fails or panics. it waits a
+//! little longer after each failure in a row so a workspace that's down isn't hammered.
+use crate::utils::backoff;
+use failure::{format_err, Error};
+use log::{error, info};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MIN_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+// a run that lasted at least this long counts as having worked, so the delay starts over
+const STABLE_AFTER: Duration = Duration::from_secs(5 * 60);
+
+pub fn supervise(name: &str, mut run: impl FnMut() -> Result<(), Error>) -> ! {
+    let mut failures = 0;
+    loop {
+        info!("starting {}", name);
+        let started = Instant::now();
+        let result = catch_unwind(AssertUnwindSafe(&mut run))
+            .unwrap_or_else(|_| Err(format_err!("panicked")));
+
+        if started.elapsed() >= STABLE_AFTER {
+            failures = 0;
+        }
+        match result {
+            Ok(()) => info!("{} stopped", name),
+            Err(e) => error!("{} failed: {}", name, e),
+        }
+
+        let delay = backoff(MIN_DELAY, failures, MAX_DELAY);
+        failures += 1;
+        info!("restarting {} in {}s", name, delay.as_secs());
+        thread::sleep(delay);
+    }
+}
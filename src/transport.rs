@@ -0,0 +1,54 @@
+//! how the bot hears about messages, picked at startup with RUSTY_TRANSPORT
+//!
+//! - rtm (the default): the real time messaging websocket, which slack no longer offers new apps
+//! - socket: a socket mode websocket, opened with the workspace's app-level token
+//! - http: slack posts events to us, see events_api
+use crate::workspace::Workspace;
+use failure::{bail, Error};
+
+const TRANSPORT_ENV_VAR: &str = "RUSTY_TRANSPORT";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Rtm,
+    SocketMode,
+    Http,
+}
+
+impl std::str::FromStr for Transport {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Transport, Error> {
+        match s.to_lowercase().as_str() {
+            "rtm" => Ok(Transport::Rtm),
+            "socket" | "socket-mode" => Ok(Transport::SocketMode),
+            "http" | "events" => Ok(Transport::Http),
+            _ => bail!(
+                "unknown transport '{}', expected one of: rtm, socket, http",
+                s
+            ),
+        }
+    }
+}
+
+impl Transport {
+    pub fn from_env() -> Result<Transport, Error> {
+        match std::env::var(TRANSPORT_ENV_VAR) {
+            Ok(s) => s.parse(),
+            Err(_) => Ok(Transport::Rtm),
+        }
+    }
+
+    // make sure the workspace's secret has what this transport needs
+    pub fn check(self, workspace: &Workspace) -> Result<(), Error> {
+        match self {
+            Transport::SocketMode if workspace.app_token.is_none() => {
+                bail!("socket mode needs a SLACK_APP_TOKEN")
+            }
+            Transport::Http if workspace.signing_secret.is_none() => {
+                bail!("the events api needs a SLACK_SIGNING_SECRET")
+            }
+            _ => Ok(()),
+        }
+    }
+}
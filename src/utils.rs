@@ -1,91 +1,293 @@
 //! utility functions that don't belong anywhere else
+use crate::workspace::Workspace;
 use crate::SlackChannel;
-use log::{debug, info};
+use failure::{bail, format_err, Error};
+use log::{debug, error, info};
+use rand::Rng;
+use regex::Regex;
 use reqwest::blocking::Client;
-use serde_json::Value;
+use serde_json::{json, Value};
 use slack_api::reactions::AddRequest;
 use slack_api::users::{InfoRequest, InfoResponse};
+use std::time::Duration;
 
-pub fn get_slack_token_from_env_var() -> String {
-    // get bot token from environment variables
-    let target_env_var = "SLACKBOT_TOKEN_SECRET";
-    let mut api_key_json: String = String::new();
-    for (k, v) in std::env::vars() {
-        if k == target_env_var {
-            api_key_json = v;
-        }
-    }
+// what's kept in a workspace's secret. only the bot token is required, the others are for the
+// transports that need them.
+pub struct SlackSecrets {
+    pub token: String,
+    // an app-level token, for socket mode
+    pub app_token: Option<String>,
+    // for checking that requests to the events api really came from slack
+    pub signing_secret: Option<String>,
+}
 
-    if api_key_json.is_empty() {
-        eprintln!(
-            "no {} environment variable found!\nPlease set this env var and try again.",
+pub fn slack_secrets_from_env_var(target_env_var: &str) -> Result<SlackSecrets, Error> {
+    // get bot token from environment variables
+    let api_key_json = match std::env::var(target_env_var) {
+        Ok(v) if !v.is_empty() => v,
+        _ => bail!(
+            "no {} environment variable found! Please set this env var and try again.",
             target_env_var
-        );
-        std::process::exit(1);
-    }
+        ),
+    };
 
     // we need to set the token env var as a json object becasue of the way fargate consumes
     // secrets. This process extracts the token we need from the json object.
-    let slackbot_token_json: Value = serde_json::from_str(&api_key_json).unwrap();
-    String::from(slackbot_token_json["SLACKBOT_TOKEN"].as_str().unwrap())
+    let slackbot_token_json: Value = serde_json::from_str(&api_key_json)
+        .map_err(|e| format_err!("{} isn't valid json: {}", target_env_var, e))?;
+    let token = match slackbot_token_json["SLACKBOT_TOKEN"].as_str() {
+        Some(token) => String::from(token),
+        None => bail!("{} has no SLACKBOT_TOKEN", target_env_var),
+    };
+    Ok(SlackSecrets {
+        token,
+        app_token: slackbot_token_json["SLACK_APP_TOKEN"]
+            .as_str()
+            .map(String::from),
+        signing_secret: slackbot_token_json["SLACK_SIGNING_SECRET"]
+            .as_str()
+            .map(String::from),
+    })
+}
+
+// the bot's own user id, for noticing when it's mentioned
+pub fn get_bot_user_id(token: &str) -> Result<String, Error> {
+    let api_client = make_client();
+    let res = slack_api::sync::auth::test(&api_client, token)
+        .map_err(|e| format_err!("auth.test failed: {}", e))?;
+    res.user_id
+        .ok_or_else(|| format_err!("auth.test didn't say who the bot is"))
 }
 
 fn make_client() -> Client {
     slack_api::sync::requests::default_client().unwrap()
 }
 
-pub fn bot_say(channel: SlackChannel, msg: &str) {
-    let api_client = make_client();
-    let token = get_slack_token_from_env_var();
+pub fn bot_say(workspace: &Workspace, channel: &SlackChannel, msg: &str) {
+    match workspace.channels.id(channel) {
+        Some(chan_id) => bot_say_to(workspace, &chan_id, msg),
+        None => error!(
+            "{} has no #{} channel, dropping message: {}",
+            workspace.name, channel, msg
+        ),
+    }
+}
+
+// like bot_say, but for when all we have is the channel id from an incoming message
+pub fn bot_say_to(workspace: &Workspace, chan_id: &str, msg: &str) {
+    say_to(workspace, chan_id, &format!("```{}```", msg))
+}
 
-    let chan_id = channel.to_string();
-    let bot_msg = format!("```{}```", msg);
+// where the answer to a command goes: the channel it was sent in, or for a slash command, back to
+// just the person who used it through the command's response_url
+#[derive(Debug, Clone)]
+pub enum ReplyTo {
+    Channel(String),
+    Ephemeral(String),
+}
+
+// answer a command, formatted like bot_say
+pub fn reply(workspace: &Workspace, reply_to: &ReplyTo, msg: &str) {
+    match reply_to {
+        ReplyTo::Channel(chan_id) => bot_say_to(workspace, chan_id, msg),
+        ReplyTo::Ephemeral(response_url) => {
+            let body = json!({
+                "response_type": "ephemeral",
+                "text": format!("```{}```", msg),
+            });
+            let res = make_client().post(response_url).json(&body).send();
+            info!("{:?}", res);
+        }
+    }
+}
+
+// post a plain message, without the code block bot_say puts around it
+pub fn say_to(workspace: &Workspace, chan_id: &str, text: &str) {
+    let api_client = make_client();
+    let token = &workspace.token;
 
     let msg = slack_api::sync::chat::PostMessageRequest {
-        channel: &chan_id,
-        text: &bot_msg,
+        channel: chan_id,
+        text,
         as_user: Some(true),
         ..Default::default()
     };
 
     info!(
         "{:?}",
-        slack_api::sync::chat::post_message(&api_client, &token, &msg)
+        slack_api::sync::chat::post_message(&api_client, token, &msg)
     );
 }
 
-pub fn add_reaction(request: AddRequest) {
+// slack_api's PostMessageRequest predates block kit, so messages with blocks are posted directly
+pub fn post_blocks(
+    client: &Client,
+    token: &str,
+    channel: &str,
+    text: &str,
+    blocks: &Value,
+) -> Result<Value, Error> {
+    post_message(
+        client,
+        token,
+        &json!({
+            "channel": channel,
+            "text": text,
+            "blocks": blocks,
+            "as_user": true,
+            "unfurl_links": false,
+        }),
+    )
+}
+
+// reply in the thread under the message with timestamp `thread_ts`
+pub fn post_reply(
+    client: &Client,
+    token: &str,
+    channel: &str,
+    thread_ts: &str,
+    text: &str,
+) -> Result<Value, Error> {
+    post_message(
+        client,
+        token,
+        &json!({
+            "channel": channel,
+            "text": text,
+            "thread_ts": thread_ts,
+            "as_user": true,
+            "unfurl_links": false,
+        }),
+    )
+}
+
+fn post_message(client: &Client, token: &str, body: &Value) -> Result<Value, Error> {
+    let res: Value = client
+        .post("https://slack.com/api/chat.postMessage")
+        .bearer_auth(token)
+        .json(body)
+        .send()?
+        .json()?;
+    debug!("{:?}", res);
+
+    if res["ok"].as_bool() != Some(true) {
+        bail!("chat.postMessage failed: {}", res["error"]);
+    }
+    Ok(res)
+}
+
+pub fn add_reaction(token: &str, request: AddRequest) {
     info!("adding reaction");
     let api_client = make_client();
-    let token = get_slack_token_from_env_var();
-    let res = slack_api::sync::reactions::add(&api_client, &token, &request);
+    let res = slack_api::sync::reactions::add(&api_client, token, &request);
     debug!("{:?}", res);
 }
 
-pub fn get_user_info(user_id: &str) -> Option<InfoResponse> {
+pub fn get_user_info(token: &str, user_id: &str) -> Option<InfoResponse> {
     let api_client = make_client();
-    let token = get_slack_token_from_env_var();
     let info_request = InfoRequest { user: user_id };
-    if let Ok(user_info) = slack_api::sync::users::info(&api_client, &token, &info_request) {
-        Some(user_info)
-    } else {
-        None
-    }
+    slack_api::sync::users::info(&api_client, token, &info_request).ok()
 }
 
-pub fn get_user_handle(user_id: &str) -> Option<String> {
-    if let Some(info) = get_user_info(user_id) {
+pub fn get_user_handle(token: &str, user_id: &str) -> Option<String> {
+    if let Some(info) = get_user_info(token, user_id) {
         info.user.unwrap().name
     } else {
         None
     }
 }
 
-pub fn get_user_real_name(user_id: &str) -> Option<String> {
-    if let Some(info) = get_user_info(user_id) {
+pub fn get_user_real_name(token: &str, user_id: &str) -> Option<String> {
+    if let Some(info) = get_user_info(token, user_id) {
         let user_real_name = info.user.unwrap().real_name.unwrap();
         Some(user_real_name)
     } else {
         None
     }
 }
+
+pub fn parse_slack_url(url: &str) -> &str {
+    info!("parser got url: {}", url);
+    // slash commands get urls as they were typed, unless the command is set up to escape them
+    if url.len() == 1 || !url.starts_with('<') {
+        return url;
+    }
+
+    if url.contains('|') {
+        // when slack gets a bare url like example.com it attempts to present it as a full link
+        // in the UI and modifies it to slacks syntax for a link. for example:
+        // example.com becomes <http://example.com|example.com>
+        let url_string_parts: Vec<&str> = url.split('|').collect();
+        let url_string = url_string_parts[0];
+
+        // ignore the leading angle bracket
+        &url_string[1..]
+    } else {
+        // a url sent to slack as http://example.com will look like <http://example.com> by the time
+        // the bot sees it. We'll keep everything except the leading and trailing angle brackets.
+        &url[1..url.len() - 1]
+    }
+}
+
+// pull the attributes out of every <`name`> tag in a page, like <link> or <meta>. this is nowhere
+// near a real html parser, but those tags are simple enough that it doesn't need to be.
+pub fn html_tags(html: &str, name: &str) -> Vec<Vec<(String, String)>> {
+    let tag_re = Regex::new(&format!(r"(?is)<{}\b[^>]*>", regex::escape(name))).unwrap();
+    let attr_re =
+        Regex::new(r#"(?s)([a-zA-Z-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap();
+
+    tag_re
+        .find_iter(html)
+        .map(|tag| {
+            attr_re
+                .captures_iter(tag.as_str())
+                .map(|cap| {
+                    let value = cap
+                        .get(2)
+                        .or_else(|| cap.get(3))
+                        .or_else(|| cap.get(4))
+                        .map(|m| m.as_str())
+                        .unwrap_or_default();
+                    (cap[1].to_lowercase(), value.replace("&amp;", "&"))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// strip tags and entities from an html description so it reads as plain text
+pub fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#8217;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&");
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// how long to wait before trying something again after it has failed `failures` times in a row.
+// the delay doubles with each failure and gets up to 25% added so that things which broke together
+// don't all retry together.
+pub fn backoff(base: Duration, failures: u32, max: Duration) -> Duration {
+    let factor = 2u32.saturating_pow(failures.min(16));
+    let delay = base.checked_mul(factor).unwrap_or(max).min(max);
+    let jitter = rand::thread_rng().gen_range(0, delay.as_millis() as u64 / 4 + 1);
+    delay + Duration::from_millis(jitter)
+}
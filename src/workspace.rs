@@ -0,0 +1,333 @@
+//! workspaces, so that one bot can serve more than one slack
+//!
+//! workspaces are listed in a toml file, read from the path in RUSTY_WORKSPACES_PATH (default:
+//! workspaces.toml). without the file there's a single workspace with its token in
+//! SLACKBOT_TOKEN_SECRET, its feeds in RUSTY_FEEDS_PATH, its state in RUSTY_STATE_PATH and its
+//! library in RUSTY_LIBRARY_STORE, see library_store.
+//!
+//! ```toml
+//! [[workspace]]
+//! name = "sanantoniodevs"
+//! token_var = "SLACKBOT_TOKEN_SECRET"
+//! feeds = "feeds.toml"
+//! state = "state.json"
+//! library_store = "sqlite"
+//! library_path = "library.db"
+//! ```
+use crate::channels::ChannelDirectory;
+use crate::config::{feeds_path, load_subscriptions, Subscriptions};
+use crate::library_store::{library_path, library_store_kind, open_store, LibraryStore, StoreKind};
+use crate::state::state_path;
+use crate::utils::{get_bot_user_id, slack_secrets_from_env_var};
+use crate::SlackChannel;
+use failure::{bail, format_err, Error};
+use log::{info, warn};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+const WORKSPACES_PATH_ENV_VAR: &str = "RUSTY_WORKSPACES_PATH";
+const DEFAULT_WORKSPACES_PATH: &str = "workspaces.toml";
+const DEFAULT_TOKEN_VAR: &str = "SLACKBOT_TOKEN_SECRET";
+const DEFAULT_LIBRARY_TABLE: &str = "library";
+
+// everything that differs from one workspace to the next
+pub struct Workspace {
+    pub name: String,
+    pub token: String,
+    // the bot's user id in this workspace
+    pub bot_id: String,
+    pub app_token: Option<String>,
+    pub signing_secret: Option<String>,
+    pub channels: ChannelDirectory,
+    // where !put saves links and !last finds them
+    pub library: Box<dyn LibraryStore>,
+    pub state_path: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WorkspaceFile {
+    #[serde(default, rename = "workspace")]
+    workspaces: Vec<WorkspaceEntry>,
+}
+
+// a workspace as written in the file, before its token, feeds and channels have been loaded
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceEntry {
+    pub name: String,
+    // the environment variable holding the workspace's token, in the same format as
+    // SLACKBOT_TOKEN_SECRET. it's json with SLACKBOT_TOKEN, and SLACK_APP_TOKEN or
+    // SLACK_SIGNING_SECRET when socket mode or the events api are used.
+    #[serde(default = "default_token_var")]
+    token_var: String,
+    // the workspace's subscription file, see config
+    feeds: String,
+    // defaults to state-<name>.json, each workspace needs its own
+    #[serde(default)]
+    state: Option<String>,
+    // dynamodb, sqlite or memory
+    #[serde(default = "default_library_store")]
+    library_store: String,
+    // the table, when the library is in dynamodb. defaults to library-<name>
+    #[serde(default)]
+    library_table: Option<String>,
+    // the database file, when the library is in sqlite. defaults to library-<name>.db
+    #[serde(default)]
+    library_path: Option<String>,
+}
+
+fn default_token_var() -> String {
+    String::from(DEFAULT_TOKEN_VAR)
+}
+
+fn default_library_store() -> String {
+    String::from("dynamodb")
+}
+
+impl WorkspaceEntry {
+    // the workspace the bot has always run against, for when there's no workspaces file
+    fn from_env() -> WorkspaceEntry {
+        WorkspaceEntry {
+            name: String::from("default"),
+            token_var: default_token_var(),
+            feeds: feeds_path(),
+            state: Some(state_path()),
+            library_store: library_store_kind(),
+            library_table: Some(String::from(DEFAULT_LIBRARY_TABLE)),
+            library_path: Some(library_path()),
+        }
+    }
+
+    fn state_path(&self) -> String {
+        self.state
+            .clone()
+            .unwrap_or_else(|| format!("state-{}.json", self.name))
+    }
+
+    fn library_table(&self) -> String {
+        self.library_table
+            .clone()
+            .unwrap_or_else(|| format!("library-{}", self.name))
+    }
+
+    fn library_path(&self) -> String {
+        self.library_path
+            .clone()
+            .unwrap_or_else(|| format!("library-{}.db", self.name))
+    }
+
+    // read the workspace's token and feeds and look up its channels, making sure every channel
+    // the feeds post to exists
+    pub fn load(&self) -> Result<(Workspace, Subscriptions), Error> {
+        let secrets = slack_secrets_from_env_var(&self.token_var)?;
+        let token = secrets.token;
+        let subscriptions = load_subscriptions(&self.feeds)?;
+        info!(
+            "loaded {} feed subscriptions and {} channel digests for {}",
+            subscriptions.feeds.len(),
+            subscriptions.digests.len(),
+            self.name
+        );
+
+        let channels = ChannelDirectory::load(&token)
+            .map_err(|e| format_err!("unable to list slack channels: {}", e))?;
+        let missing = channels.missing(subscriptions.channels());
+        if !missing.is_empty() {
+            let names: Vec<String> = missing.iter().map(|c| format!("#{}", c)).collect();
+            bail!(
+                "feeds refer to channels that don't exist: {}",
+                names.join(", ")
+            );
+        }
+        for channel in channels.missing(&[SlackChannel::bot_spam(), SlackChannel::library()]) {
+            warn!(
+                "{} has no #{} channel, messages meant for it will be dropped",
+                self.name, channel
+            );
+        }
+
+        let kind: StoreKind = self.library_store.parse()?;
+        let library = open_store(kind, &self.library_table(), &self.library_path())?;
+
+        let bot_id = get_bot_user_id(&token)?;
+        let workspace = Workspace {
+            name: self.name.clone(),
+            token,
+            bot_id,
+            app_token: secrets.app_token,
+            signing_secret: secrets.signing_secret,
+            channels,
+            library,
+            state_path: self.state_path(),
+        };
+        Ok((workspace, subscriptions))
+    }
+}
+
+pub fn workspaces_path() -> String {
+    std::env::var(WORKSPACES_PATH_ENV_VAR).unwrap_or_else(|_| String::from(DEFAULT_WORKSPACES_PATH))
+}
+
+// read and validate the workspaces file, or fall back to the single workspace from the
+// environment if there isn't one
+pub fn load_workspace_entries() -> Result<Vec<WorkspaceEntry>, Error> {
+    let path = workspaces_path();
+    if !Path::new(&path).exists() {
+        info!("no {}, running a single workspace", path);
+        return Ok(vec![WorkspaceEntry::from_env()]);
+    }
+
+    info!("loading workspaces from {}", path);
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| format_err!("unable to read {}: {}", path, e))?;
+    parse_workspaces(&contents).map_err(|e| format_err!("{}: {}", path, e))
+}
+
+fn parse_workspaces(contents: &str) -> Result<Vec<WorkspaceEntry>, Error> {
+    let file: WorkspaceFile = toml::from_str(contents)?;
+    if file.workspaces.is_empty() {
+        bail!("no workspaces listed");
+    }
+
+    let mut names = HashSet::new();
+    let mut token_vars = HashSet::new();
+    let mut state_paths = HashSet::new();
+    let mut library_tables = HashSet::new();
+    let mut library_paths = HashSet::new();
+    for (idx, entry) in file.workspaces.iter().enumerate() {
+        let name = format!("workspace #{} ({})", idx + 1, entry.name);
+        if entry.name.is_empty() {
+            bail!("{}: needs a name", name);
+        }
+        if !names.insert(entry.name.clone()) {
+            bail!("{}: duplicate name", name);
+        }
+        if !token_vars.insert(entry.token_var.clone()) {
+            bail!("{}: token_var is already used by another workspace", name);
+        }
+        if !state_paths.insert(entry.state_path()) {
+            bail!("{}: state is already used by another workspace", name);
+        }
+        let kind: StoreKind = entry
+            .library_store
+            .parse()
+            .map_err(|e| format_err!("{}: {}", name, e))?;
+        if kind == StoreKind::DynamoDb && !library_tables.insert(entry.library_table()) {
+            bail!(
+                "{}: library_table is already used by another workspace",
+                name
+            );
+        }
+        if kind == StoreKind::Sqlite && !library_paths.insert(entry.library_path()) {
+            bail!(
+                "{}: library_path is already used by another workspace",
+                name
+            );
+        }
+    }
+    Ok(file.workspaces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(contents: &str) -> String {
+        match parse_workspaces(contents) {
+            Ok(_) => panic!("parsed {}", contents),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    const FIRST: &str = r#"
+[[workspace]]
+name = "first"
+feeds = "feeds.toml"
+"#;
+
+    // the error for a file with the first workspace and then this one
+    fn second_error(entry: &str) -> String {
+        error(&format!("{}\n[[workspace]]\n{}", FIRST, entry))
+    }
+
+    #[test]
+    fn defaults() {
+        let entries = parse_workspaces(&format!(
+            "{}\n[[workspace]]\nname = \"second\"\ntoken_var = \"SECOND\"\nfeeds = \"f.toml\"",
+            FIRST
+        ))
+        .unwrap();
+        assert_eq!(entries[0].token_var, DEFAULT_TOKEN_VAR);
+        assert_eq!(entries[0].state_path(), "state-first.json");
+        assert_eq!(entries[0].library_table(), "library-first");
+        assert_eq!(entries[0].library_path(), "library-first.db");
+        assert_eq!(entries[1].library_table(), "library-second");
+    }
+
+    #[test]
+    fn no_workspaces() {
+        assert_eq!(error(""), "no workspaces listed");
+    }
+
+    #[test]
+    fn duplicates() {
+        let err = second_error("name = \"first\"\ntoken_var = \"SECOND\"\nfeeds = \"f.toml\"");
+        assert_eq!(err, "workspace #2 (first): duplicate name");
+        let err = second_error("name = \"second\"\nfeeds = \"f.toml\"");
+        assert!(
+            err.starts_with("workspace #2 (second): token_var"),
+            "{}",
+            err
+        );
+        let err = second_error(
+            "name = \"second\"\ntoken_var = \"SECOND\"\nfeeds = \"f.toml\"\n\
+             state = \"state-first.json\"",
+        );
+        assert!(err.starts_with("workspace #2 (second): state"), "{}", err);
+        let err = second_error(
+            "name = \"second\"\ntoken_var = \"SECOND\"\nfeeds = \"f.toml\"\n\
+             library_table = \"library-first\"",
+        );
+        assert!(
+            err.starts_with("workspace #2 (second): library_table"),
+            "{}",
+            err
+        );
+        let err = error(
+            "[[workspace]]\nname = \"first\"\nfeeds = \"f.toml\"\nlibrary_store = \"sqlite\"\n\
+             library_path = \"library.db\"\n\
+             [[workspace]]\nname = \"second\"\ntoken_var = \"SECOND\"\nfeeds = \"f.toml\"\n\
+             library_store = \"sqlite\"\nlibrary_path = \"library.db\"",
+        );
+        assert!(
+            err.starts_with("workspace #2 (second): library_path"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn shared_names_in_other_stores_are_fine() {
+        // a table name only matters to dynamodb, and a path only to sqlite
+        parse_workspaces(
+            "[[workspace]]\nname = \"first\"\nfeeds = \"f.toml\"\nlibrary_table = \"shared\"\n\
+             [[workspace]]\nname = \"second\"\ntoken_var = \"SECOND\"\nfeeds = \"f.toml\"\n\
+             library_store = \"memory\"\nlibrary_table = \"shared\"",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn bad_entries() {
+        let err = second_error("name = \"\"\ntoken_var = \"SECOND\"\nfeeds = \"f.toml\"");
+        assert_eq!(err, "workspace #2 (): needs a name");
+        let err = second_error(
+            "name = \"second\"\ntoken_var = \"SECOND\"\nfeeds = \"f.toml\"\n\
+             library_store = \"postgres\"",
+        );
+        assert!(err.starts_with("workspace #2 (second): "), "{}", err);
+        // unknown keys are a toml error, before any workspace is looked at
+        assert!(parse_workspaces(&format!("{}colour = \"blue\"", FIRST)).is_err());
+    }
+}